@@ -0,0 +1,126 @@
+/// How a typed trigger's case differs from the stored trigger, used to carry
+/// that case over to the expansion of `Snippet::propagate_case` snippets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseStyle {
+    /// Insert the content unchanged.
+    #[default]
+    AsIs,
+    /// Uppercase the first letter of the content.
+    Capitalized,
+    /// Uppercase the whole content.
+    Upper,
+}
+
+impl CaseStyle {
+    /// Compares the typed trigger with the stored one. All-uppercase typing
+    /// (of more than one letter) uppercases the content; an uppercase first
+    /// letter where the trigger has a lowercase one capitalises it.
+    pub fn detect(trigger: &str, typed: &str) -> Self {
+        let typed_letters: Vec<char> = typed.chars().filter(|ch| ch.is_alphabetic()).collect();
+        let trigger_letters: Vec<char> = trigger.chars().filter(|ch| ch.is_alphabetic()).collect();
+
+        let (Some(first_typed), Some(first_trigger)) = (typed_letters.first(), trigger_letters.first()) else {
+            return CaseStyle::AsIs;
+        };
+
+        let all_upper = |letters: &[char]| letters.iter().all(|ch| ch.is_uppercase());
+        if typed_letters.len() > 1 && all_upper(&typed_letters) && !all_upper(&trigger_letters) {
+            CaseStyle::Upper
+        } else if first_typed.is_uppercase() && !first_trigger.is_uppercase() {
+            CaseStyle::Capitalized
+        } else {
+            CaseStyle::AsIs
+        }
+    }
+
+    /// Applies the style to `content`. For HTML only text is changed; tags and
+    /// character references are left alone.
+    pub fn apply(self, content: &str, is_html: bool) -> String {
+        if self == CaseStyle::AsIs {
+            return content.to_string();
+        }
+
+        let mut result = String::with_capacity(content.len());
+        let mut capitalized = false;
+        let mut in_tag = false;
+        let mut in_entity = false;
+
+        for ch in content.chars() {
+            if is_html {
+                if in_tag {
+                    in_tag = ch != '>';
+                    result.push(ch);
+                    continue;
+                }
+                if in_entity {
+                    in_entity = ch != ';' && !ch.is_whitespace();
+                    result.push(ch);
+                    continue;
+                }
+                if ch == '<' || ch == '&' {
+                    in_tag = ch == '<';
+                    in_entity = ch == '&';
+                    result.push(ch);
+                    continue;
+                }
+            }
+
+            match self {
+                CaseStyle::Upper => result.extend(ch.to_uppercase()),
+                CaseStyle::Capitalized if !capitalized && ch.is_alphabetic() => {
+                    result.extend(ch.to_uppercase());
+                    capitalized = true;
+                }
+                _ => result.push(ch),
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_how_the_trigger_was_typed() {
+        assert_eq!(CaseStyle::detect("sig", "sig"), CaseStyle::AsIs);
+        assert_eq!(CaseStyle::detect("sig", "Sig"), CaseStyle::Capitalized);
+        assert_eq!(CaseStyle::detect("sig", "SIG"), CaseStyle::Upper);
+        assert_eq!(CaseStyle::detect("sig", "sIG"), CaseStyle::AsIs);
+    }
+
+    #[test]
+    fn a_single_uppercase_letter_only_capitalizes() {
+        assert_eq!(CaseStyle::detect("a", "A"), CaseStyle::Capitalized);
+        assert_eq!(CaseStyle::detect(";a", ";A"), CaseStyle::Capitalized);
+    }
+
+    #[test]
+    fn typing_the_stored_case_keeps_the_content() {
+        assert_eq!(CaseStyle::detect("API", "API"), CaseStyle::AsIs);
+        assert_eq!(CaseStyle::detect("Brb", "Brb"), CaseStyle::AsIs);
+        assert_eq!(CaseStyle::detect(";;", ";;"), CaseStyle::AsIs);
+    }
+
+    #[test]
+    fn applies_to_plain_text() {
+        assert_eq!(CaseStyle::AsIs.apply("be right back", false), "be right back");
+        assert_eq!(CaseStyle::Capitalized.apply("... be right back", false), "... Be right back");
+        assert_eq!(CaseStyle::Upper.apply("straße", false), "STRASSE");
+    }
+
+    #[test]
+    fn leaves_html_tags_and_character_references_alone() {
+        let html = r#"<a href="x">be</a> &amp; &nbsp;right"#;
+        assert_eq!(CaseStyle::Upper.apply(html, true), r#"<a href="x">BE</a> &amp; &nbsp;RIGHT"#);
+        assert_eq!(CaseStyle::Capitalized.apply(html, true), r#"<a href="x">Be</a> &amp; &nbsp;right"#);
+        assert_eq!(CaseStyle::Capitalized.apply("&lt;b&gt;", true), "&lt;B&gt;");
+    }
+
+    #[test]
+    fn plain_text_angle_brackets_are_text() {
+        assert_eq!(CaseStyle::Upper.apply("<b> & co", false), "<B> & CO");
+    }
+}
@@ -1,6 +1,30 @@
-use clipboard_rs::{Clipboard, ClipboardContext};
+use clipboard_rs::{Clipboard, ClipboardContent, ClipboardContext, ContentFormat};
 use std::error::Error;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
 
+/// Clipboard contents saved before an expansion overwrites them.
+pub struct ClipboardSnapshot {
+    contents: Vec<ClipboardContent>,
+}
+
+/// Reads and writes the clipboard for expansions that paste or use the
+/// `{{clipboard}}` variables.
+pub trait ClipboardBackend: Send {
+    fn get_text(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+    fn get_html(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+    fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Writes HTML together with a plain-text alternative so that pasting
+    /// into applications without rich text support still inserts something.
+    fn set_html(&mut self, html: &str, plain_text: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Saves what is on the clipboard. Flavours that cannot be read are
+    /// skipped.
+    fn snapshot(&self) -> ClipboardSnapshot;
+    /// Puts a snapshot back, clearing the clipboard if it was empty.
+    fn restore(&mut self, snapshot: ClipboardSnapshot) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// The system clipboard through clipboard-rs.
 pub struct ClipboardHandler {
     ctx: ClipboardContext,
 }
@@ -12,18 +36,139 @@ impl ClipboardHandler {
         })
     }
 
-    pub fn _set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    pub fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.ctx.clear()?;
+        Ok(())
+    }
+}
+
+impl ClipboardBackend for ClipboardHandler {
+    fn get_text(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.ctx.get_text()?)
+    }
+
+    fn get_html(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.ctx.get_html()?)
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         self.ctx.set_text(text.to_string())?;
         Ok(())
     }
 
-    pub fn set_html(&mut self, html: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-        self.ctx.set_html(html.to_string())?;
+    fn set_html(&mut self, html: &str, plain_text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.ctx.set(vec![
+            ClipboardContent::Text(plain_text.to_string()),
+            ClipboardContent::Html(html.to_string()),
+        ])?;
         Ok(())
     }
 
-    pub fn clear(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        self.ctx.clear()?;
+    /// Saves the text, HTML and image flavours currently on the clipboard.
+    fn snapshot(&self) -> ClipboardSnapshot {
+        let mut contents = Vec::new();
+
+        if self.ctx.has(ContentFormat::Text) {
+            if let Ok(text) = self.ctx.get_text() {
+                contents.push(ClipboardContent::Text(text));
+            }
+        }
+        if self.ctx.has(ContentFormat::Html) {
+            if let Ok(html) = self.ctx.get_html() {
+                contents.push(ClipboardContent::Html(html));
+            }
+        }
+        if self.ctx.has(ContentFormat::Image) {
+            if let Ok(image) = self.ctx.get_image() {
+                contents.push(ClipboardContent::Image(image));
+            }
+        }
+
+        ClipboardSnapshot { contents }
+    }
+
+    fn restore(&mut self, snapshot: ClipboardSnapshot) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if snapshot.contents.is_empty() {
+            return self.clear();
+        }
+        self.ctx.set(snapshot.contents)?;
+        Ok(())
+    }
+}
+
+/// What a `MockClipboard` holds.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockClipboardContents {
+    pub text: Option<String>,
+    pub html: Option<String>,
+}
+
+/// An in-memory clipboard with text and HTML flavours, for running
+/// expansions without a display.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub struct MockClipboard {
+    contents: Arc<Mutex<MockClipboardContents>>,
+}
+
+#[cfg(test)]
+impl MockClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shared handle to the contents, still usable after the clipboard has
+    /// been handed to a `KeyboardHandler`.
+    pub fn contents(&self) -> Arc<Mutex<MockClipboardContents>> {
+        Arc::clone(&self.contents)
+    }
+}
+
+#[cfg(test)]
+impl ClipboardBackend for MockClipboard {
+    fn get_text(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.contents.lock().unwrap().text.clone().ok_or_else(|| "Clipboard has no text".into())
+    }
+
+    fn get_html(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        self.contents.lock().unwrap().html.clone().ok_or_else(|| "Clipboard has no HTML".into())
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.text = Some(text.to_string());
+        contents.html = None;
+        Ok(())
+    }
+
+    fn set_html(&mut self, html: &str, plain_text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.text = Some(plain_text.to_string());
+        contents.html = Some(html.to_string());
+        Ok(())
+    }
+
+    fn snapshot(&self) -> ClipboardSnapshot {
+        let contents = self.contents.lock().unwrap();
+        let text = contents.text.clone().map(ClipboardContent::Text);
+        let html = contents.html.clone().map(ClipboardContent::Html);
+        ClipboardSnapshot {
+            contents: text.into_iter().chain(html).collect(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: ClipboardSnapshot) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.text = None;
+        contents.html = None;
+        for content in snapshot.contents {
+            match content {
+                ClipboardContent::Text(text) => contents.text = Some(text),
+                ClipboardContent::Html(html) => contents.html = Some(html),
+                _ => {}
+            }
+        }
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::config::Database;
-use crate::snippet::Snippet;
-use crate::snippet_settings::SnippetSettings;
+use crate::fill_in_form::FormRequest;
+use crate::insertion_profile::InsertionProfile;
+use crate::keyboard_handler::KeyboardHandler;
+use crate::snippet::{InsertMethod, Snippet, SnippetOptions};
+use crate::snippet_settings::{SettingsOptions, SnippetSettings};
 use tauri::State;
 
 #[tauri::command]
@@ -16,20 +20,30 @@ pub fn get_snippet_by_id(id: String, db: State<Arc<Database>>) -> Result<Option<
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn create_snippet(
     trigger: String,
     content: String,
     description: Option<String>,
     is_html: bool,
     tags: Vec<String>,
+    options: Option<SnippetOptions>,
     db: State<Arc<Database>>,
+    keyboard: State<Arc<KeyboardHandler>>,
 ) -> Result<Snippet, String> {
-    let snippet = Snippet::new(trigger, content, description, tags, is_html);
+    let mut snippet = Snippet::new(trigger, content, description, tags, is_html);
+    if let Some(options) = options {
+        options.apply_to(&mut snippet);
+    }
+    snippet.validate()?;
+    snippet.validate_references(&|trigger| db.get_snippet_by_trigger(trigger))?;
     db.create_snippet(&snippet)?;
+    keyboard.upsert_snippet(snippet.clone());
     Ok(snippet)
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn update_snippet(
     id: String,
     trigger: String,
@@ -37,14 +51,58 @@ pub fn update_snippet(
     description: Option<String>,
     tags: Vec<String>,
     is_html: bool,
+    options: Option<SnippetOptions>,
     db: State<Arc<Database>>,
+    keyboard: State<Arc<KeyboardHandler>>,
 ) -> Result<(), String> {
-    db.update_snippet(&id, trigger, content, description, tags, is_html)
+    let mut snippet = db
+        .get_snippet_by_id(&id)?
+        .ok_or_else(|| "Snippet not found".to_string())?;
+    if snippet.trigger != trigger {
+        ensure_unreferenced(&db, &snippet, "rename")?;
+    }
+    snippet.update(trigger, content, description, tags, is_html);
+    if let Some(options) = options {
+        options.apply_to(&mut snippet);
+    }
+    snippet.validate()?;
+    snippet.validate_references(&|trigger| db.get_snippet_by_trigger(trigger))?;
+    db.update_snippet(&snippet)?;
+    keyboard.upsert_snippet(snippet);
+    Ok(())
 }
 
 #[tauri::command]
-pub fn delete_snippet(id: String, db: State<Arc<Database>>) -> Result<(), String> {
-    db.delete_snippet(&id)
+pub fn delete_snippet(
+    id: String,
+    db: State<Arc<Database>>,
+    keyboard: State<Arc<KeyboardHandler>>,
+) -> Result<(), String> {
+    if let Some(snippet) = db.get_snippet_by_id(&id)? {
+        ensure_unreferenced(&db, &snippet, "delete")?;
+    }
+    db.delete_snippet(&id)?;
+    keyboard.remove_snippet(&id);
+    Ok(())
+}
+
+/// Fails when other snippets include `snippet`, since renaming or deleting it
+/// would leave their `{{snippet:...}}` references dangling.
+fn ensure_unreferenced(db: &Database, snippet: &Snippet, action: &str) -> Result<(), String> {
+    let referencing = db.get_referencing_snippets(snippet)?;
+    if referencing.is_empty() {
+        return Ok(());
+    }
+    let triggers: Vec<String> = referencing
+        .iter()
+        .map(|other| format!("'{}'", other.trigger))
+        .collect();
+    Err(format!(
+        "Cannot {} '{}': it is used by {}",
+        action,
+        snippet.trigger,
+        triggers.join(", ")
+    ))
 }
 
 #[tauri::command]
@@ -58,8 +116,14 @@ pub fn export_snippets(db: State<Arc<Database>>) -> Result<String, String> {
 }
 
 #[tauri::command]
-pub fn import_snippets(json: String, db: State<Arc<Database>>) -> Result<usize, String> {
-    db.import_snippets(&json)
+pub fn import_snippets(
+    json: String,
+    db: State<Arc<Database>>,
+    keyboard: State<Arc<KeyboardHandler>>,
+) -> Result<usize, String> {
+    let imported_count = db.import_snippets(&json)?;
+    keyboard.reload_snippets()?;
+    Ok(imported_count)
 }
 
 #[tauri::command]
@@ -68,11 +132,102 @@ pub fn increment_usage(id: String, db: State<Arc<Database>>) -> Result<(), Strin
 }
 
 #[tauri::command]
-pub fn update_snippet_settings(time_delay_ms: u64, db: State<Arc<Database>>) -> Result<(), String> {
-    db.update_snippet_settings(time_delay_ms)
+pub fn update_snippet_settings(
+    time_delay_ms: u64,
+    options: Option<SettingsOptions>,
+    db: State<Arc<Database>>,
+    keyboard: State<Arc<KeyboardHandler>>,
+) -> Result<(), String> {
+    let mut settings = db.get_snippet_settings()?;
+    settings.update(time_delay_ms);
+    if let Some(options) = options {
+        options.apply_to(&mut settings);
+    }
+    db.update_snippet_settings(&settings)?;
+    keyboard.update_settings(settings);
+    Ok(())
 }
 
 #[tauri::command]
 pub fn get_snippets_settings(db: State<Arc<Database>>) -> Result<SnippetSettings, String> {
     db.get_snippet_settings()
-}
\ No newline at end of file
+}
+
+#[tauri::command]
+pub fn get_insertion_profiles(db: State<Arc<Database>>) -> Result<Vec<InsertionProfile>, String> {
+    db.get_insertion_profiles()
+}
+
+#[tauri::command]
+pub fn create_insertion_profile(
+    app_class: String,
+    paste_chord: String,
+    insert_method: Option<InsertMethod>,
+    key_delay_ms: Option<u64>,
+    paste_delay_ms: Option<u64>,
+    clipboard_restore_delay_ms: Option<u64>,
+    db: State<Arc<Database>>,
+) -> Result<InsertionProfile, String> {
+    let profile = InsertionProfile::new(
+        app_class,
+        paste_chord,
+        insert_method,
+        key_delay_ms,
+        paste_delay_ms,
+        clipboard_restore_delay_ms,
+    );
+    profile.validate()?;
+    db.create_insertion_profile(&profile)?;
+    Ok(profile)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn update_insertion_profile(
+    id: String,
+    app_class: String,
+    paste_chord: String,
+    insert_method: Option<InsertMethod>,
+    key_delay_ms: Option<u64>,
+    paste_delay_ms: Option<u64>,
+    clipboard_restore_delay_ms: Option<u64>,
+    db: State<Arc<Database>>,
+) -> Result<(), String> {
+    let mut profile = db
+        .get_insertion_profile_by_id(&id)?
+        .ok_or_else(|| "Insertion profile not found".to_string())?;
+    profile.update(
+        app_class,
+        paste_chord,
+        insert_method,
+        key_delay_ms,
+        paste_delay_ms,
+        clipboard_restore_delay_ms,
+    );
+    profile.validate()?;
+    db.update_insertion_profile(&profile)
+}
+
+#[tauri::command]
+pub fn delete_insertion_profile(id: String, db: State<Arc<Database>>) -> Result<(), String> {
+    db.delete_insertion_profile(&id)
+}
+
+#[tauri::command]
+pub fn get_form_request(keyboard: State<Arc<KeyboardHandler>>) -> Option<FormRequest> {
+    keyboard.forms().current()
+}
+
+#[tauri::command]
+pub fn submit_form(
+    id: String,
+    values: HashMap<String, String>,
+    keyboard: State<Arc<KeyboardHandler>>,
+) -> Result<(), String> {
+    keyboard.forms().submit(&id, values)
+}
+
+#[tauri::command]
+pub fn cancel_form(id: String, keyboard: State<Arc<KeyboardHandler>>) -> Result<(), String> {
+    keyboard.forms().cancel(&id)
+}
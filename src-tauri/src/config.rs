@@ -1,20 +1,149 @@
-use crate::snippet::Snippet;
-use crate::snippet_settings::SnippetSettings;
-use rusqlite::{params, OptionalExtension, Connection, Result as SqlResult};
+use crate::insertion_profile::InsertionProfile;
+use crate::snippet::{InsertMethod, MatchMode, Snippet};
+use crate::snippet_settings::{SnippetSettings, DEFAULT_DELIMITERS};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, OptionalExtension, Connection, Result as SqlResult, Row};
 use std::path::PathBuf;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+const SNIPPET_COLUMNS: &str =
+    "id, trigger, content, description, tags, created_at, updated_at, usage_count, is_html, priority, match_mode, expand_on_delimiter, propagate_case, plain_text, \
+     insert_method";
+
+const SETTINGS_COLUMNS: &str =
+    "time_delay_ms, created_at, updated_at, expand_on_delimiter, delimiters, suppress_after_undo, \
+     clipboard_restore_delay_ms, paste_threshold_chars, pre_expansion_delay_ms, key_delay_ms, post_delete_delay_ms, \
+     clipboard_settle_delay_ms, paste_delay_ms, post_expansion_delay_ms";
+
+const PROFILE_COLUMNS: &str =
+    "id, app_class, paste_chord, insert_method, key_delay_ms, paste_delay_ms, clipboard_restore_delay_ms, \
+     created_at, updated_at";
+
 pub struct Database {
     conn: Mutex<Connection>,
 }
 
+fn row_to_snippet(row: &Row) -> SqlResult<Snippet> {
+    let tags_str: String = row.get(4)?;
+    let tags: Vec<String> = serde_json::from_str(&tags_str).unwrap_or_default();
+
+    Ok(Snippet {
+        id: row.get(0)?,
+        trigger: row.get(1)?,
+        content: row.get(2)?,
+        description: row.get(3)?,
+        tags,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        usage_count: row.get(7)?,
+        is_html: row.get(8)?,
+        priority: row.get(9)?,
+        match_mode: row.get(10)?,
+        expand_on_delimiter: row.get(11)?,
+        propagate_case: row.get(12)?,
+        plain_text: row.get(13)?,
+        insert_method: row.get(14)?,
+    })
+}
+
+fn row_to_snippet_settings(row: &Row) -> SqlResult<SnippetSettings> {
+    Ok(SnippetSettings {
+        id: "1".to_string(),
+        time_delay_ms: row.get(0)?,
+        created_at: row.get(1)?,
+        updated_at: row.get(2)?,
+        expand_on_delimiter: row.get(3)?,
+        delimiters: row.get(4)?,
+        suppress_after_undo: row.get(5)?,
+        clipboard_restore_delay_ms: row.get(6)?,
+        paste_threshold_chars: row.get(7)?,
+        pre_expansion_delay_ms: row.get(8)?,
+        key_delay_ms: row.get(9)?,
+        post_delete_delay_ms: row.get(10)?,
+        clipboard_settle_delay_ms: row.get(11)?,
+        paste_delay_ms: row.get(12)?,
+        post_expansion_delay_ms: row.get(13)?,
+    })
+}
+
+fn row_to_insertion_profile(row: &Row) -> SqlResult<InsertionProfile> {
+    Ok(InsertionProfile {
+        id: row.get(0)?,
+        app_class: row.get(1)?,
+        paste_chord: row.get(2)?,
+        insert_method: row.get(3)?,
+        key_delay_ms: row.get(4)?,
+        paste_delay_ms: row.get(5)?,
+        clipboard_restore_delay_ms: row.get(6)?,
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
+
+impl ToSql for MatchMode {
+    fn to_sql(&self) -> SqlResult<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for MatchMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        MatchMode::parse(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for InsertMethod {
+    fn to_sql(&self) -> SqlResult<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for InsertMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        InsertMethod::parse(value.as_str()?).ok_or(FromSqlError::InvalidType)
+    }
+}
+
+/// Adds `column` to `table` when it is missing, so databases created by older
+/// versions pick up new fields without losing data.
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let exists: bool = conn
+        .query_row(
+            &format!("SELECT EXISTS(SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1)", table),
+            [column],
+            |row| row.get(0),
+        )
+        .map_err(|e| format!("Failed to inspect table {}: {}", table, e))?;
+
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )
+        .map_err(|e| format!("Failed to add column {}.{}: {}", table, column, e))?;
+    }
+
+    Ok(())
+}
+
 impl Database {
     pub fn new() -> Result<Self, String> {
         let path = Self::get_db_path();
         let conn = Connection::open(&path)
             .map_err(|e| format!("Failed to open database: {}", e))?;
+        Self::with_connection(conn)
+    }
 
+    /// A fresh database that lives only as long as the value, for tests.
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
         let db = Database {
             conn: Mutex::new(conn),
         };
@@ -44,12 +173,25 @@ impl Database {
                 created_at INTEGER NOT NULL,
                 updated_at INTEGER NOT NULL,
                 usage_count INTEGER NOT NULL DEFAULT 0,
-                is_html BOOLEAN NOT NULL DEFAULT FALSE
+                is_html BOOLEAN NOT NULL DEFAULT FALSE,
+                priority INTEGER NOT NULL DEFAULT 0,
+                match_mode TEXT NOT NULL DEFAULT 'anywhere',
+                expand_on_delimiter BOOLEAN,
+                propagate_case BOOLEAN NOT NULL DEFAULT FALSE,
+                plain_text TEXT,
+                insert_method TEXT NOT NULL DEFAULT 'auto'
             )",
             [],
         )
         .map_err(|e| format!("Failed to create table: {}", e))?;
 
+        ensure_column(&conn, "snippets", "priority", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn, "snippets", "match_mode", "TEXT NOT NULL DEFAULT 'anywhere'")?;
+        ensure_column(&conn, "snippets", "expand_on_delimiter", "BOOLEAN")?;
+        ensure_column(&conn, "snippets", "propagate_case", "BOOLEAN NOT NULL DEFAULT FALSE")?;
+        ensure_column(&conn, "snippets", "plain_text", "TEXT")?;
+        ensure_column(&conn, "snippets", "insert_method", "TEXT NOT NULL DEFAULT 'auto'")?;
+
         // Create index on trigger for faster lookups
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_trigger ON snippets(trigger)",
@@ -68,6 +210,23 @@ impl Database {
         )
         .map_err(|e| format!("Failed to create table: {}", e))?;
 
+        ensure_column(&conn, "snippet_settings", "expand_on_delimiter", "BOOLEAN NOT NULL DEFAULT FALSE")?;
+        ensure_column(
+            &conn,
+            "snippet_settings",
+            "delimiters",
+            &format!("TEXT NOT NULL DEFAULT '{}'", DEFAULT_DELIMITERS),
+        )?;
+        ensure_column(&conn, "snippet_settings", "suppress_after_undo", "BOOLEAN NOT NULL DEFAULT TRUE")?;
+        ensure_column(&conn, "snippet_settings", "clipboard_restore_delay_ms", "INTEGER NOT NULL DEFAULT 100")?;
+        ensure_column(&conn, "snippet_settings", "paste_threshold_chars", "INTEGER NOT NULL DEFAULT 200")?;
+        ensure_column(&conn, "snippet_settings", "pre_expansion_delay_ms", "INTEGER NOT NULL DEFAULT 150")?;
+        ensure_column(&conn, "snippet_settings", "key_delay_ms", "INTEGER NOT NULL DEFAULT 10")?;
+        ensure_column(&conn, "snippet_settings", "post_delete_delay_ms", "INTEGER NOT NULL DEFAULT 100")?;
+        ensure_column(&conn, "snippet_settings", "clipboard_settle_delay_ms", "INTEGER NOT NULL DEFAULT 100")?;
+        ensure_column(&conn, "snippet_settings", "paste_delay_ms", "INTEGER NOT NULL DEFAULT 50")?;
+        ensure_column(&conn, "snippet_settings", "post_expansion_delay_ms", "INTEGER NOT NULL DEFAULT 100")?;
+
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -78,38 +237,60 @@ impl Database {
         )
         .map_err(|e| format!("Failed to initialize snippet_settings: {}", e))?;
 
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS insertion_profiles (
+                id TEXT PRIMARY KEY,
+                app_class TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                paste_chord TEXT NOT NULL,
+                insert_method TEXT,
+                key_delay_ms INTEGER,
+                paste_delay_ms INTEGER,
+                clipboard_restore_delay_ms INTEGER,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            [],
+        )
+        .map_err(|e| format!("Failed to create table: {}", e))?;
+
         Ok(())
     }
 
     pub fn get_snippet_settings(&self) -> Result<SnippetSettings, String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
         let mut stmt = conn
-            .prepare("SELECT time_delay_ms, created_at, updated_at FROM snippet_settings WHERE id = 1")
+            .prepare(&format!("SELECT {} FROM snippet_settings WHERE id = 1", SETTINGS_COLUMNS))
             .map_err(|e| format!("Failed to prepare statement: {}", e))?;
         let snippet_settings = stmt
-            .query_row([],|row| {
-                Ok(SnippetSettings {
-                    id: "1".to_string(),
-                    time_delay_ms: row.get(0)?,
-                    created_at: row.get(1)?,
-                    updated_at: row.get(2)?,
-                })
-            })
+            .query_row([], row_to_snippet_settings)
             .optional()
             .map_err(|e| format!("Failed to query snippet: {}", e))?;
 
         snippet_settings.ok_or_else(|| "Snippet settings not found".to_string())
     }
 
-    pub fn update_snippet_settings(&self, time_delay_ms: u64) -> Result<(), String> {
+    pub fn update_snippet_settings(&self, settings: &SnippetSettings) -> Result<(), String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
         conn.execute(
-            "UPDATE snippet_settings SET time_delay_ms = ?1, updated_at = ?2 WHERE id = 1",
-            params![&time_delay_ms, &timestamp],
+            "UPDATE snippet_settings SET time_delay_ms = ?1, updated_at = ?2, expand_on_delimiter = ?3, delimiters = ?4, suppress_after_undo = ?5, \
+             clipboard_restore_delay_ms = ?6, paste_threshold_chars = ?7, pre_expansion_delay_ms = ?8, key_delay_ms = ?9, \
+             post_delete_delay_ms = ?10, clipboard_settle_delay_ms = ?11, paste_delay_ms = ?12, post_expansion_delay_ms = ?13 \
+             WHERE id = 1",
+            params![
+                settings.time_delay_ms,
+                settings.updated_at,
+                settings.expand_on_delimiter,
+                &settings.delimiters,
+                settings.suppress_after_undo,
+                settings.clipboard_restore_delay_ms,
+                settings.paste_threshold_chars,
+                settings.pre_expansion_delay_ms,
+                settings.key_delay_ms,
+                settings.post_delete_delay_ms,
+                settings.clipboard_settle_delay_ms,
+                settings.paste_delay_ms,
+                settings.post_expansion_delay_ms
+            ],
         )
         .map_err(|e| format!("Failed to update snippet: {}", e))?;
 
@@ -120,26 +301,11 @@ impl Database {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
         let mut stmt = conn
-            .prepare("SELECT id, trigger, content, description, tags, created_at, updated_at, usage_count, is_html FROM snippets ORDER BY updated_at DESC")
+            .prepare(&format!("SELECT {} FROM snippets ORDER BY updated_at DESC", SNIPPET_COLUMNS))
             .map_err(|e| format!("Failed to prepare statement: {}", e))?;
 
         let snippets = stmt
-            .query_map([], |row| {
-                let tags_str: String = row.get(4)?;
-                let tags: Vec<String> = serde_json::from_str(&tags_str).unwrap_or_default();
-
-                Ok(Snippet {
-                    id: row.get(0)?,
-                    trigger: row.get(1)?,
-                    content: row.get(2)?,
-                    description: row.get(3)?,
-                    tags,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                    usage_count: row.get(7)?,
-                    is_html: row.get(8)?,
-                })
-            })
+            .query_map([], row_to_snippet)
             .map_err(|e| format!("Failed to query snippets: {}", e))?
             .collect::<SqlResult<Vec<Snippet>>>()
             .map_err(|e| format!("Failed to collect snippets: {}", e))?;
@@ -151,32 +317,41 @@ impl Database {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
         let mut stmt = conn
-            .prepare("SELECT id, trigger, content, description, tags, created_at, updated_at, usage_count, is_html FROM snippets WHERE id = ?1")
+            .prepare(&format!("SELECT {} FROM snippets WHERE id = ?1", SNIPPET_COLUMNS))
             .map_err(|e| format!("Failed to prepare statement: {}", e))?;
 
         let snippet = stmt
-            .query_row([id], |row| {
-                let tags_str: String = row.get(4)?;
-                let tags: Vec<String> = serde_json::from_str(&tags_str).unwrap_or_default();
-
-                Ok(Snippet {
-                    id: row.get(0)?,
-                    trigger: row.get(1)?,
-                    content: row.get(2)?,
-                    description: row.get(3)?,
-                    tags,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                    usage_count: row.get(7)?,
-                    is_html: row.get(8)?,
-                })
-            })
+            .query_row([id], row_to_snippet)
             .optional()
             .map_err(|e| format!("Failed to query snippet: {}", e))?;
 
         Ok(snippet)
     }
 
+    pub fn get_snippet_by_trigger(&self, trigger: &str) -> Result<Option<Snippet>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM snippets WHERE trigger = ?1", SNIPPET_COLUMNS))
+            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+        let snippet = stmt
+            .query_row([trigger], row_to_snippet)
+            .optional()
+            .map_err(|e| format!("Failed to query snippet: {}", e))?;
+
+        Ok(snippet)
+    }
+
+    /// Other snippets that include `snippet` through `{{snippet:...}}`.
+    pub fn get_referencing_snippets(&self, snippet: &Snippet) -> Result<Vec<Snippet>, String> {
+        Ok(self
+            .get_all_snippets()?
+            .into_iter()
+            .filter(|other| other.id != snippet.id && other.references(&snippet.trigger))
+            .collect())
+    }
+
     pub fn create_snippet(&self, snippet: &Snippet) -> Result<(), String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
@@ -197,7 +372,10 @@ impl Database {
             .map_err(|e| format!("Failed to serialize tags: {}", e))?;
 
         conn.execute(
-            "INSERT INTO snippets (id, trigger, content, description, tags, created_at, updated_at, usage_count, is_html) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            &format!(
+                "INSERT INTO snippets ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                SNIPPET_COLUMNS
+            ),
             params![
                 &snippet.id,
                 &snippet.trigger,
@@ -207,7 +385,13 @@ impl Database {
                 snippet.created_at,
                 snippet.updated_at,
                 snippet.usage_count,
-                snippet.is_html
+                snippet.is_html,
+                snippet.priority,
+                snippet.match_mode,
+                snippet.expand_on_delimiter,
+                snippet.propagate_case,
+                &snippet.plain_text,
+                snippet.insert_method
             ],
         )
         .map_err(|e| format!("Failed to insert snippet: {}", e))?;
@@ -215,22 +399,14 @@ impl Database {
         Ok(())
     }
 
-    pub fn update_snippet(
-        &self,
-        id: &str,
-        trigger: String,
-        content: String,
-        description: Option<String>,
-        tags: Vec<String>,
-        is_html: bool,
-    ) -> Result<(), String> {
+    pub fn update_snippet(&self, snippet: &Snippet) -> Result<(), String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
         // Check for duplicate trigger (excluding current snippet)
         let exists: bool = conn
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM snippets WHERE trigger = ?1 AND id != ?2)",
-                params![&trigger, id],
+                params![&snippet.trigger, &snippet.id],
                 |row| row.get(0),
             )
             .map_err(|e| format!("Failed to check for duplicate: {}", e))?;
@@ -239,17 +415,26 @@ impl Database {
             return Err("Another snippet with this trigger already exists".to_string());
         }
 
-        let tags_json = serde_json::to_string(&tags)
+        let tags_json = serde_json::to_string(&snippet.tags)
             .map_err(|e| format!("Failed to serialize tags: {}", e))?;
 
-        let updated_at = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
         conn.execute(
-            "UPDATE snippets SET trigger = ?1, content = ?2, description = ?3, tags = ?4, updated_at = ?5, is_html = ?7 WHERE id = ?6",
-            params![&trigger, &content, &description, &tags_json, updated_at, id, is_html],
+            "UPDATE snippets SET trigger = ?1, content = ?2, description = ?3, tags = ?4, updated_at = ?5, is_html = ?7, priority = ?8, match_mode = ?9, expand_on_delimiter = ?10, propagate_case = ?11, plain_text = ?12, insert_method = ?13 WHERE id = ?6",
+            params![
+                &snippet.trigger,
+                &snippet.content,
+                &snippet.description,
+                &tags_json,
+                snippet.updated_at,
+                &snippet.id,
+                snippet.is_html,
+                snippet.priority,
+                snippet.match_mode,
+                snippet.expand_on_delimiter,
+                snippet.propagate_case,
+                &snippet.plain_text,
+                snippet.insert_method
+            ],
         )
         .map_err(|e| format!("Failed to update snippet: {}", e))?;
 
@@ -279,34 +464,20 @@ impl Database {
         let search_pattern = format!("%{}%", query);
 
         let mut stmt = conn
-            .prepare(
-                "SELECT id, trigger, content, description, tags, created_at, updated_at, usage_count, is_html
+            .prepare(&format!(
+                "SELECT {}
                  FROM snippets 
                  WHERE trigger LIKE ?1 
                     OR content LIKE ?1 
                     OR description LIKE ?1 
                     OR tags LIKE ?1 
-                 ORDER BY updated_at DESC"
-            )
+                 ORDER BY updated_at DESC",
+                SNIPPET_COLUMNS
+            ))
             .map_err(|e| format!("Failed to prepare statement: {}", e))?;
 
         let snippets = stmt
-            .query_map([&search_pattern], |row| {
-                let tags_str: String = row.get(4)?;
-                let tags: Vec<String> = serde_json::from_str(&tags_str).unwrap_or_default();
-
-                Ok(Snippet {
-                    id: row.get(0)?,
-                    trigger: row.get(1)?,
-                    content: row.get(2)?,
-                    description: row.get(3)?,
-                    tags,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                    usage_count: row.get(7)?,
-                    is_html: row.get(8)?,
-                })
-            })
+            .query_map([&search_pattern], row_to_snippet)
             .map_err(|e| format!("Failed to query snippets: {}", e))?
             .collect::<SqlResult<Vec<Snippet>>>()
             .map_err(|e| format!("Failed to collect snippets: {}", e))?;
@@ -336,6 +507,122 @@ impl Database {
         Ok(imported_count)
     }
 
+    pub fn get_insertion_profiles(&self) -> Result<Vec<InsertionProfile>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM insertion_profiles ORDER BY app_class", PROFILE_COLUMNS))
+            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+        let profiles = stmt
+            .query_map([], row_to_insertion_profile)
+            .map_err(|e| format!("Failed to query insertion profiles: {}", e))?
+            .collect::<SqlResult<Vec<InsertionProfile>>>()
+            .map_err(|e| format!("Failed to collect insertion profiles: {}", e))?;
+
+        Ok(profiles)
+    }
+
+    pub fn get_insertion_profile_by_id(&self, id: &str) -> Result<Option<InsertionProfile>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM insertion_profiles WHERE id = ?1", PROFILE_COLUMNS))
+            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+
+        let profile = stmt
+            .query_row([id], row_to_insertion_profile)
+            .optional()
+            .map_err(|e| format!("Failed to query insertion profile: {}", e))?;
+
+        Ok(profile)
+    }
+
+    pub fn create_insertion_profile(&self, profile: &InsertionProfile) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM insertion_profiles WHERE app_class = ?1)",
+                [&profile.app_class],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to check for duplicate: {}", e))?;
+
+        if exists {
+            return Err("A profile for this application already exists".to_string());
+        }
+
+        conn.execute(
+            &format!(
+                "INSERT INTO insertion_profiles ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                PROFILE_COLUMNS
+            ),
+            params![
+                &profile.id,
+                &profile.app_class,
+                &profile.paste_chord,
+                profile.insert_method,
+                profile.key_delay_ms,
+                profile.paste_delay_ms,
+                profile.clipboard_restore_delay_ms,
+                profile.created_at,
+                profile.updated_at
+            ],
+        )
+        .map_err(|e| format!("Failed to insert insertion profile: {}", e))?;
+
+        Ok(())
+    }
+
+    pub fn update_insertion_profile(&self, profile: &InsertionProfile) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM insertion_profiles WHERE app_class = ?1 AND id != ?2)",
+                params![&profile.app_class, &profile.id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to check for duplicate: {}", e))?;
+
+        if exists {
+            return Err("Another profile for this application already exists".to_string());
+        }
+
+        conn.execute(
+            "UPDATE insertion_profiles SET app_class = ?1, paste_chord = ?2, insert_method = ?3, key_delay_ms = ?4, \
+             paste_delay_ms = ?5, clipboard_restore_delay_ms = ?6, updated_at = ?7 WHERE id = ?8",
+            params![
+                &profile.app_class,
+                &profile.paste_chord,
+                profile.insert_method,
+                profile.key_delay_ms,
+                profile.paste_delay_ms,
+                profile.clipboard_restore_delay_ms,
+                profile.updated_at,
+                &profile.id
+            ],
+        )
+        .map_err(|e| format!("Failed to update insertion profile: {}", e))?;
+
+        Ok(())
+    }
+
+    pub fn delete_insertion_profile(&self, id: &str) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+
+        let rows_affected = conn
+            .execute("DELETE FROM insertion_profiles WHERE id = ?1", [id])
+            .map_err(|e| format!("Failed to delete insertion profile: {}", e))?;
+
+        if rows_affected == 0 {
+            return Err("Insertion profile not found".to_string());
+        }
+
+        Ok(())
+    }
+
 pub fn increment_usage(&self, id: &str) -> Result<(), String> {
         let conn = self.conn.lock().map_err(|e| e.to_string())?;
 
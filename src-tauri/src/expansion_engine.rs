@@ -0,0 +1,1061 @@
+use crate::case_style::CaseStyle;
+use crate::snippet::Snippet;
+use crate::snippet_settings::SnippetSettings;
+use crate::trigger_index::{is_word_boundary, TriggerIndex, TriggerMatch};
+use rdev::{Event, EventType, Key as RdevKey};
+use std::collections::VecDeque;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_TRIGGER_LENGTH: usize = 100;
+
+/// Source of the current time, so the engine's timing can be driven by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct FakeClock {
+    now: Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Option is how macOS types many characters (Option+L is `@` on a German
+/// layout), so there Alt only makes a shortcut when the key types nothing.
+const ALT_TYPES_TEXT: bool = cfg!(target_os = "macos");
+
+/// Modifier keys that change which character a key produces, and those that
+/// turn a key into a shortcut.
+#[derive(Debug, Default)]
+struct ModifierState {
+    shift_left: bool,
+    shift_right: bool,
+    caps_lock: bool,
+    alt_gr: bool,
+    control_left: bool,
+    control_right: bool,
+    alt: bool,
+    meta_left: bool,
+    meta_right: bool,
+}
+
+impl ModifierState {
+    fn shift(&self) -> bool {
+        self.shift_left || self.shift_right
+    }
+
+    /// AltGr types characters, and Windows reports it together with a fake
+    /// left Control, so Control does not count while AltGr is down.
+    fn control(&self) -> bool {
+        (self.control_left || self.control_right) && !self.alt_gr
+    }
+
+    fn meta(&self) -> bool {
+        self.meta_left || self.meta_right
+    }
+
+    /// Whether a key pressed now is a shortcut rather than typing, given
+    /// whether the system reported a printable character for it.
+    fn chord(&self, types_text: bool) -> bool {
+        self.control() || self.meta() || (self.alt && !(ALT_TYPES_TEXT && types_text))
+    }
+
+    /// Whether Backspace now deletes the word before the caret:
+    /// Ctrl+Backspace, or Option+Backspace on macOS.
+    fn word_delete(&self) -> bool {
+        (self.control() || self.alt) && !self.meta()
+    }
+
+    /// Records a press or release, returning `true` if `key` is a modifier.
+    fn update(&mut self, key: &RdevKey, pressed: bool) -> bool {
+        match key {
+            RdevKey::ShiftLeft => self.shift_left = pressed,
+            RdevKey::ShiftRight => self.shift_right = pressed,
+            RdevKey::AltGr => self.alt_gr = pressed,
+            RdevKey::CapsLock => {
+                if pressed {
+                    self.caps_lock = !self.caps_lock;
+                }
+            }
+            RdevKey::ControlLeft => self.control_left = pressed,
+            RdevKey::ControlRight => self.control_right = pressed,
+            RdevKey::Alt => self.alt = pressed,
+            RdevKey::MetaLeft => self.meta_left = pressed,
+            RdevKey::MetaRight => self.meta_right = pressed,
+            RdevKey::Function => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Characters typed since the buffer was last reset, and whether the text
+/// just before them ends at a word boundary.
+#[derive(Debug, Clone)]
+struct TypedBuffer {
+    text: String,
+    starts_at_boundary: bool,
+}
+
+impl TypedBuffer {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            starts_at_boundary: true,
+        }
+    }
+
+    fn push(&mut self, ch: char) {
+        self.text.push(ch);
+        if self.text.chars().count() > MAX_TRIGGER_LENGTH {
+            let removed = self.text.remove(0);
+            self.starts_at_boundary = is_word_boundary(removed);
+        }
+    }
+
+    fn pop(&mut self) {
+        self.text.pop();
+    }
+
+    /// Mirrors a word deletion: separators right before the caret go first,
+    /// then the word before them. When that reaches past the start of the
+    /// buffer the deletion stopped at a word boundary we never saw.
+    fn delete_word(&mut self) {
+        let trimmed = self.text.trim_end_matches(is_word_boundary);
+        let kept = trimmed.trim_end_matches(|ch| !is_word_boundary(ch)).len();
+        if kept == 0 {
+            self.clear();
+        } else {
+            self.text.truncate(kept);
+        }
+    }
+
+    /// Forgets the typed text because the caret moved somewhere unknown.
+    fn clear(&mut self) {
+        self.text.clear();
+        self.starts_at_boundary = true;
+    }
+
+    /// Forgets the typed text while the caret stays right after it.
+    fn reset_after_text(&mut self) {
+        if let Some(last) = self.text.chars().last() {
+            self.starts_at_boundary = is_word_boundary(last);
+        }
+        self.text.clear();
+    }
+}
+
+/// A resolved trigger, ready to be replaced by its snippet.
+#[derive(Debug)]
+pub struct Expansion {
+    pub snippet: Snippet,
+    /// Characters to erase: the trigger plus any separator typed after it.
+    pub delete_count: usize,
+    pub trailing: Option<char>,
+    pub case_style: CaseStyle,
+    /// The trigger as it was typed, restored if the expansion is undone.
+    pub typed: String,
+}
+
+/// What the most recent expansion replaced, so that pressing Backspace right
+/// after it can put the typed trigger back.
+#[derive(Debug)]
+pub struct LastExpansion {
+    pub typed: String,
+    pub trailing: Option<char>,
+    /// Characters the expansion inserted, including the re-typed separator.
+    pub inserted_chars: usize,
+}
+
+impl Expansion {
+    fn new(found: TriggerMatch<'_>) -> Self {
+        let case_style = if found.snippet.propagate_case {
+            CaseStyle::detect(&found.snippet.trigger, &found.typed)
+        } else {
+            CaseStyle::AsIs
+        };
+
+        Self {
+            snippet: found.snippet.clone(),
+            delete_count: found.length + found.trailing.map_or(0, |_| 1),
+            trailing: found.trailing,
+            case_style,
+            typed: found.typed,
+        }
+    }
+}
+
+/// What the caller has to do in response to an event.
+#[derive(Debug)]
+pub enum Action {
+    /// Delete `Expansion::delete_count` characters and insert the snippet.
+    Expand(Expansion),
+    /// Put the trigger of the last expansion back.
+    Undo(LastExpansion),
+}
+
+/// Input the caller is about to synthesize, announced with
+/// `ExpansionEngine::expect` so the listener can tell it apart from the
+/// user's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expected {
+    Key(RdevKey),
+    Char(char),
+}
+
+impl Expected {
+    fn matches(&self, key: &RdevKey, name: Option<&str>) -> bool {
+        match self {
+            Expected::Key(expected) => expected == key,
+            // Shortcut keys such as the V of Ctrl+V report a control
+            // character as their name, so the key itself is compared too.
+            Expected::Char(ch) => {
+                name.is_some_and(|name| name.chars().eq(std::iter::once(*ch)))
+                    || key_to_char(key, &ModifierState::default()) == Some(ch.to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+/// Trigger detection as a state machine: it is fed input events, reads time
+/// only from its `Clock` and answers with the `Action` to perform. It never
+/// touches the database, the clipboard or the keyboard itself, so it can be
+/// driven headlessly.
+///
+/// After a key the caller should wait until `deadline()` and then call
+/// `tick()`, which expands the buffer if the user stopped typing there.
+///
+/// Once an action has been returned the engine is injecting: the input the
+/// caller synthesizes comes back through the listener and is ignored, and no
+/// other action is produced until `finish_expansion` or `finish_undo`. Key
+/// presses the caller did not announce with `expect`, and mouse clicks, are
+/// the user's; they mark the action as aborted so the caller can stop
+/// injecting before it edits the wrong text. While the caller waits for the
+/// user, for example in a fill-in form, it can `suspend` the action so that
+/// typing elsewhere is neither recorded nor counted as interference.
+pub struct ExpansionEngine {
+    index: TriggerIndex,
+    settings: SnippetSettings,
+    clock: Arc<dyn Clock>,
+    buffer: TypedBuffer,
+    modifiers: ModifierState,
+    last_key_time: Instant,
+    deadline: Option<Instant>,
+    last_expansion: Option<LastExpansion>,
+    injecting: bool,
+    expected: VecDeque<Expected>,
+    aborted: bool,
+    suspended: bool,
+}
+
+impl ExpansionEngine {
+    pub fn new(snippets: Vec<Snippet>, settings: SnippetSettings, clock: Arc<dyn Clock>) -> Self {
+        let last_key_time = clock.now();
+        Self {
+            index: TriggerIndex::new(snippets),
+            settings,
+            clock,
+            buffer: TypedBuffer::new(),
+            modifiers: ModifierState::default(),
+            last_key_time,
+            deadline: None,
+            last_expansion: None,
+            injecting: false,
+            expected: VecDeque::new(),
+            aborted: false,
+            suspended: false,
+        }
+    }
+
+    pub fn reload_snippets(&mut self, snippets: Vec<Snippet>) {
+        self.index = TriggerIndex::new(snippets);
+    }
+
+    pub fn upsert_snippet(&mut self, snippet: Snippet) {
+        self.index.insert(snippet);
+    }
+
+    pub fn remove_snippet(&mut self, id: &str) {
+        self.index.remove(id);
+    }
+
+    pub fn settings(&self) -> &SnippetSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SnippetSettings) {
+        self.settings = settings;
+    }
+
+    /// When the buffer should be checked again with `tick()`, if at all.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn buffer(&self) -> &str {
+        &self.buffer.text
+    }
+
+    pub fn clear_buffer(&mut self) {
+        self.buffer.clear();
+        self.deadline = None;
+    }
+
+    pub fn handle(&mut self, event: &Event) -> Option<Action> {
+        match event.event_type {
+            EventType::KeyRelease(key) => {
+                self.modifiers.update(&key, false);
+                None
+            }
+            EventType::KeyPress(key) if self.suspended => {
+                self.modifiers.update(&key, true);
+                None
+            }
+            EventType::KeyPress(key) if self.injecting => {
+                self.injected_key_press(key, event.name.as_deref());
+                None
+            }
+            EventType::ButtonPress(_) if self.injecting && !self.suspended => {
+                self.aborted = true;
+                None
+            }
+            _ if self.injecting => None,
+            EventType::KeyPress(key) => self.key_press(key, event.name.as_deref()),
+            EventType::ButtonPress(_) => {
+                self.clear_buffer();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    pub fn key_press(&mut self, key: RdevKey, name: Option<&str>) -> Option<Action> {
+        if self.injecting {
+            return None;
+        }
+        if self.modifiers.update(&key, true) {
+            return None;
+        }
+
+        // Shortcuts such as Ctrl+C, Alt+F or Super+L type nothing, and many
+        // of them move the caret, select, cut or paste, so the buffer can no
+        // longer be trusted. Word deletion is the exception: its effect on
+        // the text is known.
+        if self.modifiers.chord(name.and_then(printable_char).is_some()) {
+            self.last_expansion = None;
+            if key == RdevKey::Backspace && self.modifiers.word_delete() {
+                let now = self.clock.now();
+                self.buffer.delete_word();
+                self.last_key_time = now;
+                self.schedule(now);
+            } else {
+                self.clear_buffer();
+            }
+            return None;
+        }
+
+        // Only the key right after an expansion may undo it.
+        let last_expansion = self.last_expansion.take();
+        if let (RdevKey::Backspace, Some(last_expansion)) = (&key, last_expansion) {
+            self.deadline = None;
+            return self.start(Action::Undo(last_expansion));
+        }
+
+        let now = self.clock.now();
+
+        // The pause before this key was long enough to expand but nothing
+        // matched, so the text typed before it can no longer become a
+        // trigger.
+        if !self.buffer.text.is_empty()
+            && now.duration_since(self.last_key_time) >= self.expansion_delay()
+            && !self.awaits_separator()
+        {
+            self.buffer.reset_after_text();
+            self.deadline = None;
+        }
+
+        self.last_key_time = now;
+
+        let typed = self.typed_char(&key, name);
+        let delimiter = match key {
+            RdevKey::Return | RdevKey::KpReturn => Some('\n'),
+            RdevKey::Tab => Some('\t'),
+            RdevKey::Backspace => None,
+            _ => typed,
+        };
+
+        if let Some(delimiter) = delimiter.filter(|&delimiter| self.settings.is_delimiter(delimiter)) {
+            if let Some(expansion) = self.delimiter_expansion(delimiter) {
+                self.clear_buffer();
+                return self.start(Action::Expand(expansion));
+            }
+        }
+
+        match key {
+            RdevKey::Backspace => self.buffer.pop(),
+
+            // Moving the caret or extending a selection with Shift, deleting
+            // forwards, and Shift+Insert pasting.
+            RdevKey::Return | RdevKey::Tab | RdevKey::Escape | RdevKey::LeftArrow
+            | RdevKey::RightArrow | RdevKey::UpArrow | RdevKey::DownArrow | RdevKey::Home
+            | RdevKey::End | RdevKey::PageUp | RdevKey::PageDown | RdevKey::Delete
+            | RdevKey::Insert => {
+                self.clear_buffer();
+                return None;
+            }
+
+            _ => match typed {
+                Some(ch) => self.buffer.push(ch),
+                None => return None,
+            },
+        }
+
+        self.schedule(now);
+        None
+    }
+
+    /// Sets the deadline for expanding the buffer as it is after a key
+    /// pressed at `now`.
+    fn schedule(&mut self, now: Instant) {
+        self.deadline = if self.buffer.text.is_empty() {
+            None
+        } else {
+            Some(now + self.expansion_delay())
+        };
+    }
+
+    /// Expands the trigger at the end of the buffer once the deadline has
+    /// passed. Without a match the typed text is forgotten, unless it ends in
+    /// a whole-word trigger still waiting for its separator.
+    pub fn tick(&mut self) -> Option<Action> {
+        if self.injecting {
+            return None;
+        }
+        let deadline = self.deadline?;
+        if self.clock.now() < deadline {
+            return None;
+        }
+        self.deadline = None;
+
+        let expansion = self
+            .index
+            .resolve(&self.buffer.text, self.buffer.starts_at_boundary, None)
+            .map(Expansion::new);
+        match expansion {
+            Some(expansion) => {
+                self.buffer.clear();
+                self.start(Action::Expand(expansion))
+            }
+            None => {
+                if !self.awaits_separator() {
+                    self.buffer.reset_after_text();
+                }
+                None
+            }
+        }
+    }
+
+    /// Enters the injecting state for an action handed to the caller.
+    fn start(&mut self, action: Action) -> Option<Action> {
+        self.injecting = true;
+        self.expected.clear();
+        self.aborted = false;
+        Some(action)
+    }
+
+    /// Announces a key press the caller is about to synthesize.
+    pub fn expect(&mut self, expected: Expected) {
+        if self.injecting {
+            self.expected.push_back(expected);
+        }
+    }
+
+    /// Announces text the caller is about to type.
+    pub fn expect_text(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.expect(match ch {
+                '\n' => Expected::Key(RdevKey::Return),
+                '\t' => Expected::Key(RdevKey::Tab),
+                _ => Expected::Char(ch),
+            });
+        }
+    }
+
+    /// Ignores all input until `resume`, while the current action waits for
+    /// the user.
+    pub fn suspend(&mut self) {
+        if self.injecting {
+            self.suspended = true;
+        }
+    }
+
+    /// Goes back to watching for interference once the user is done; nothing
+    /// that happened while suspended counts against the action.
+    pub fn resume(&mut self) {
+        self.suspended = false;
+        self.expected.clear();
+        self.aborted = false;
+    }
+
+    /// Whether an action handed to the caller has not been finished yet.
+    #[cfg(test)]
+    pub fn injecting(&self) -> bool {
+        self.injecting
+    }
+
+    /// Whether the user interfered with the current action.
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Sorts a key press seen while injecting into the caller's own input
+    /// and the user's. Announced presses may be skipped (some text is
+    /// delivered without key events), but never reordered.
+    fn injected_key_press(&mut self, key: RdevKey, name: Option<&str>) {
+        if self.modifiers.update(&key, true) {
+            return;
+        }
+
+        match self.expected.iter().position(|expected| expected.matches(&key, name)) {
+            Some(index) => {
+                self.expected.drain(..=index);
+            }
+            None => self.aborted = true,
+        }
+    }
+
+    /// Leaves the injecting state, returning whether the action was aborted.
+    fn finish(&mut self) -> bool {
+        self.injecting = false;
+        self.suspended = false;
+        self.expected.clear();
+        self.clear_buffer();
+        std::mem::take(&mut self.aborted)
+    }
+
+    /// Called once an `Action::Expand` has been carried out. Whatever reached
+    /// the buffer meanwhile is dropped, and `last_expansion` is remembered
+    /// when the expansion can be undone. Returns whether it was aborted.
+    pub fn finish_expansion(&mut self, last_expansion: Option<LastExpansion>) -> bool {
+        let aborted = self.finish();
+        self.last_expansion = last_expansion.filter(|_| !aborted);
+        aborted
+    }
+
+    /// Called once an `Action::Undo` has been carried out. Returns whether it
+    /// was aborted.
+    pub fn finish_undo(&mut self, last_expansion: &LastExpansion) -> bool {
+        if self.finish() {
+            return true;
+        }
+        if self.settings.suppress_after_undo {
+            // Leave the restored trigger out of the buffer so it is not
+            // expanded again straight away.
+            self.buffer.starts_at_boundary = false;
+        } else {
+            for ch in last_expansion.typed.chars().chain(last_expansion.trailing) {
+                self.buffer.push(ch);
+            }
+            // Count the restored trigger as just typed, so the next key
+            // continues it instead of finding it stale. It is not scheduled:
+            // expanding it again on its own would undo the undo.
+            self.last_key_time = self.clock.now();
+        }
+        false
+    }
+
+    fn awaits_separator(&self) -> bool {
+        self.index.awaits_separator(&self.buffer.text, self.buffer.starts_at_boundary)
+    }
+
+    /// How long the buffer has to sit idle before it is expanded. When the
+    /// matched trigger is also the start of a longer one, the user gets a
+    /// second timeout window to finish typing it.
+    fn expansion_delay(&self) -> Duration {
+        let buffer_timeout = Duration::from_millis(self.settings.time_delay_ms);
+        match self.index.resolve(&self.buffer.text, self.buffer.starts_at_boundary, None) {
+            Some(found) if found.has_longer_candidate => buffer_timeout * 2,
+            _ => buffer_timeout,
+        }
+    }
+
+    /// Finds the snippet to expand right away because `delimiter` was typed
+    /// after its trigger. Snippets without their own `expand_on_delimiter`
+    /// follow the global setting.
+    fn delimiter_expansion(&self, delimiter: char) -> Option<Expansion> {
+        let found = self
+            .index
+            .resolve(&self.buffer.text, self.buffer.starts_at_boundary, Some(delimiter))?;
+        if !found.snippet.expand_on_delimiter.unwrap_or(self.settings.expand_on_delimiter) {
+            return None;
+        }
+        Some(Expansion::new(found))
+    }
+
+    /// Resolves the character a key press produced. rdev fills `name` from the
+    /// active keyboard layout (Shift, CapsLock and AltGr included), so it is
+    /// preferred; the US-QWERTY table is only a fallback for platforms or keys
+    /// where no name is reported.
+    fn typed_char(&self, key: &RdevKey, name: Option<&str>) -> Option<char> {
+        if let Some(name) = name {
+            return printable_char(name);
+        }
+
+        if self.modifiers.alt_gr {
+            return None;
+        }
+        key_to_char(key, &self.modifiers)
+    }
+}
+
+fn key_to_char(key: &RdevKey, modifiers: &ModifierState) -> Option<char> {
+    let (plain, shifted) = match key {
+        RdevKey::KeyA => ('a', 'A'),
+        RdevKey::KeyB => ('b', 'B'),
+        RdevKey::KeyC => ('c', 'C'),
+        RdevKey::KeyD => ('d', 'D'),
+        RdevKey::KeyE => ('e', 'E'),
+        RdevKey::KeyF => ('f', 'F'),
+        RdevKey::KeyG => ('g', 'G'),
+        RdevKey::KeyH => ('h', 'H'),
+        RdevKey::KeyI => ('i', 'I'),
+        RdevKey::KeyJ => ('j', 'J'),
+        RdevKey::KeyK => ('k', 'K'),
+        RdevKey::KeyL => ('l', 'L'),
+        RdevKey::KeyM => ('m', 'M'),
+        RdevKey::KeyN => ('n', 'N'),
+        RdevKey::KeyO => ('o', 'O'),
+        RdevKey::KeyP => ('p', 'P'),
+        RdevKey::KeyQ => ('q', 'Q'),
+        RdevKey::KeyR => ('r', 'R'),
+        RdevKey::KeyS => ('s', 'S'),
+        RdevKey::KeyT => ('t', 'T'),
+        RdevKey::KeyU => ('u', 'U'),
+        RdevKey::KeyV => ('v', 'V'),
+        RdevKey::KeyW => ('w', 'W'),
+        RdevKey::KeyX => ('x', 'X'),
+        RdevKey::KeyY => ('y', 'Y'),
+        RdevKey::KeyZ => ('z', 'Z'),
+        RdevKey::Num0 => ('0', ')'),
+        RdevKey::Num1 => ('1', '!'),
+        RdevKey::Num2 => ('2', '@'),
+        RdevKey::Num3 => ('3', '#'),
+        RdevKey::Num4 => ('4', '$'),
+        RdevKey::Num5 => ('5', '%'),
+        RdevKey::Num6 => ('6', '^'),
+        RdevKey::Num7 => ('7', '&'),
+        RdevKey::Num8 => ('8', '*'),
+        RdevKey::Num9 => ('9', '('),
+        RdevKey::Minus => ('-', '_'),
+        RdevKey::Equal => ('=', '+'),
+        RdevKey::LeftBracket => ('[', '{'),
+        RdevKey::RightBracket => (']', '}'),
+        RdevKey::SemiColon => (';', ':'),
+        RdevKey::Quote => ('\'', '"'),
+        RdevKey::BackSlash => ('\\', '|'),
+        RdevKey::Comma => (',', '<'),
+        RdevKey::Dot => ('.', '>'),
+        RdevKey::Slash => ('/', '?'),
+        RdevKey::BackQuote => ('`', '~'),
+        RdevKey::Space => (' ', ' '),
+        _ => return None,
+    };
+
+    let shift = if plain.is_ascii_alphabetic() {
+        modifiers.shift() != modifiers.caps_lock
+    } else {
+        modifiers.shift()
+    };
+
+    Some(if shift { shifted } else { plain })
+}
+
+/// The character a key event's name stands for, if it is a single printable one.
+fn printable_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) if !ch.is_control() => Some(ch),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_backend::MockSource;
+    use crate::snippet::MatchMode;
+
+    const DELAY: Duration = Duration::from_millis(200);
+
+    fn snippet(trigger: &str) -> Snippet {
+        Snippet::new(trigger.to_string(), format!("<{}>", trigger), None, Vec::new(), false)
+    }
+
+    fn engine(triggers: &[&str]) -> (ExpansionEngine, Arc<FakeClock>) {
+        engine_with(triggers.iter().map(|trigger| snippet(trigger)).collect(), |_| {})
+    }
+
+    fn engine_with(
+        snippets: Vec<Snippet>,
+        configure: impl FnOnce(&mut SnippetSettings),
+    ) -> (ExpansionEngine, Arc<FakeClock>) {
+        let clock = Arc::new(FakeClock::new());
+        let mut settings = SnippetSettings::new("1".to_string(), DELAY.as_millis() as u64);
+        configure(&mut settings);
+        (ExpansionEngine::new(snippets, settings, clock.clone()), clock)
+    }
+
+    /// Expands `trigger` once the deadline passes and undoes it with
+    /// Backspace, as the keyboard handler would.
+    fn expand_and_undo(engine: &mut ExpansionEngine, clock: &FakeClock, trigger: &str) {
+        type_text(engine, trigger);
+        clock.advance(DELAY * 2);
+        let expansion = expanded(engine.tick());
+        engine.finish_expansion(Some(LastExpansion {
+            typed: expansion.typed,
+            trailing: None,
+            inserted_chars: 5,
+        }));
+        let Some(Action::Undo(last_expansion)) = press(engine, RdevKey::Backspace) else {
+            panic!("expected an undo");
+        };
+        clock.advance(DELAY);
+        assert!(!engine.finish_undo(&last_expansion));
+    }
+
+    fn press(engine: &mut ExpansionEngine, key: RdevKey) -> Option<Action> {
+        engine.handle(&MockSource::key_press(key, None))
+    }
+
+    fn release(engine: &mut ExpansionEngine, key: RdevKey) {
+        engine.handle(&MockSource::key_release(key));
+    }
+
+    fn type_text(engine: &mut ExpansionEngine, text: &str) -> Option<Action> {
+        let mut action = None;
+        for ch in text.chars() {
+            let event = MockSource::key_press(RdevKey::Unknown(ch as u32), Some(&ch.to_string()));
+            action = engine.handle(&event);
+        }
+        action
+    }
+
+    fn expanded(action: Option<Action>) -> Expansion {
+        match action {
+            Some(Action::Expand(expansion)) => expansion,
+            other => panic!("expected an expansion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expands_once_the_deadline_has_passed() {
+        let (mut engine, clock) = engine(&["sig"]);
+        let start = clock.now();
+
+        assert!(type_text(&mut engine, "sig").is_none());
+        assert_eq!(engine.deadline(), Some(start + DELAY));
+
+        clock.advance(DELAY - Duration::from_millis(1));
+        assert!(engine.tick().is_none());
+
+        clock.advance(Duration::from_millis(1));
+        let expansion = expanded(engine.tick());
+        assert_eq!(expansion.snippet.trigger, "sig");
+        assert_eq!(expansion.delete_count, 3);
+        assert_eq!(engine.deadline(), None);
+    }
+
+    #[test]
+    fn each_key_moves_the_deadline() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "s");
+        clock.advance(DELAY / 2);
+        type_text(&mut engine, "ig");
+        clock.advance(DELAY / 2);
+        assert!(engine.tick().is_none());
+
+        clock.advance(DELAY / 2);
+        expanded(engine.tick());
+    }
+
+    #[test]
+    fn waits_twice_as_long_while_a_longer_trigger_is_possible() {
+        let (mut engine, clock) = engine(&["sig", "sign"]);
+        let start = clock.now();
+
+        type_text(&mut engine, "sig");
+        assert_eq!(engine.deadline(), Some(start + DELAY * 2));
+
+        clock.advance(DELAY);
+        assert!(engine.tick().is_none());
+        clock.advance(DELAY);
+        assert_eq!(expanded(engine.tick()).snippet.trigger, "sig");
+    }
+
+    #[test]
+    fn a_long_pause_resets_the_buffer() {
+        let (mut engine, clock) = engine(&["sig", "xig"]);
+
+        type_text(&mut engine, "s");
+        clock.advance(DELAY);
+        type_text(&mut engine, "ig");
+        assert_eq!(engine.buffer(), "ig");
+
+        clock.advance(DELAY);
+        assert!(engine.tick().is_none());
+        assert_eq!(engine.buffer(), "");
+    }
+
+    #[test]
+    fn a_whole_word_trigger_survives_a_pause_before_its_separator() {
+        let whole_word = Snippet {
+            match_mode: MatchMode::WholeWord,
+            ..snippet("ad")
+        };
+        let (mut engine, clock) = engine_with(vec![whole_word], |_| {});
+
+        type_text(&mut engine, "ad");
+        clock.advance(DELAY);
+        assert!(engine.tick().is_none());
+        assert_eq!(engine.buffer(), "ad");
+
+        clock.advance(DELAY * 5);
+        type_text(&mut engine, " ");
+        assert_eq!(engine.buffer(), "ad ");
+        clock.advance(DELAY);
+        let expansion = expanded(engine.tick());
+        assert_eq!(expansion.snippet.trigger, "ad");
+        assert_eq!(expansion.trailing, Some(' '));
+        assert_eq!(expansion.delete_count, 3);
+    }
+
+    #[test]
+    fn backspace_after_an_expansion_asks_for_an_undo() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig");
+        clock.advance(DELAY);
+        let expansion = expanded(engine.tick());
+        let aborted = engine.finish_expansion(Some(LastExpansion {
+            typed: expansion.typed.clone(),
+            trailing: None,
+            inserted_chars: 5,
+        }));
+        assert!(!aborted);
+
+        match press(&mut engine, RdevKey::Backspace) {
+            Some(Action::Undo(last_expansion)) => {
+                assert_eq!(last_expansion.typed, "sig");
+                assert_eq!(last_expansion.inserted_chars, 5);
+            }
+            other => panic!("expected an undo, got {:?}", other),
+        }
+        assert!(!engine.finish_undo(&LastExpansion {
+            typed: "sig".to_string(),
+            trailing: None,
+            inserted_chars: 5,
+        }));
+    }
+
+    #[test]
+    fn typing_continues_a_restored_trigger_unless_suppressed() {
+        let snippets = vec![snippet("sig"), snippet("sign")];
+        let (mut engine, clock) = engine_with(snippets, |settings| settings.suppress_after_undo = false);
+
+        expand_and_undo(&mut engine, &clock, "sig");
+        assert_eq!(engine.buffer(), "sig");
+        assert_eq!(engine.deadline(), None);
+
+        clock.advance(DELAY / 2);
+        type_text(&mut engine, "n");
+        assert_eq!(engine.buffer(), "sign");
+        clock.advance(DELAY);
+        assert_eq!(expanded(engine.tick()).snippet.trigger, "sign");
+    }
+
+    #[test]
+    fn a_suppressed_restored_trigger_is_not_continued() {
+        let (mut engine, clock) = engine(&["sig", "sign"]);
+
+        expand_and_undo(&mut engine, &clock, "sig");
+        assert_eq!(engine.buffer(), "");
+
+        type_text(&mut engine, "n");
+        clock.advance(DELAY);
+        assert!(engine.tick().is_none());
+    }
+
+    #[test]
+    fn only_the_next_key_can_undo() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig");
+        clock.advance(DELAY);
+        expanded(engine.tick());
+        engine.finish_expansion(Some(LastExpansion {
+            typed: "sig".to_string(),
+            trailing: None,
+            inserted_chars: 5,
+        }));
+
+        type_text(&mut engine, "x");
+        assert!(press(&mut engine, RdevKey::Backspace).is_none());
+    }
+
+    #[test]
+    fn announced_input_is_ignored_while_injecting() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig");
+        clock.advance(DELAY);
+        expanded(engine.tick());
+        assert!(engine.injecting());
+
+        engine.expect(Expected::Key(RdevKey::Backspace));
+        engine.expect_text("<sig>");
+        assert!(press(&mut engine, RdevKey::Backspace).is_none());
+        assert!(type_text(&mut engine, "<sig>").is_none());
+        assert!(!engine.aborted());
+        assert_eq!(engine.buffer(), "");
+
+        assert!(!engine.finish_expansion(None));
+        assert!(!engine.injecting());
+    }
+
+    #[test]
+    fn user_input_while_injecting_aborts() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig");
+        clock.advance(DELAY);
+        expanded(engine.tick());
+
+        engine.expect_text("<sig>");
+        type_text(&mut engine, "q");
+        assert!(engine.aborted());
+        assert!(engine.tick().is_none());
+        assert!(engine.finish_expansion(None));
+        assert!(!engine.aborted());
+    }
+
+    #[test]
+    fn a_click_while_injecting_aborts() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig");
+        clock.advance(DELAY);
+        expanded(engine.tick());
+
+        engine.handle(&Event {
+            time: std::time::SystemTime::now(),
+            name: None,
+            event_type: EventType::ButtonPress(rdev::Button::Left),
+        });
+        assert!(engine.finish_expansion(None));
+    }
+
+    #[test]
+    fn alt_with_a_typed_character_is_text_only_on_macos() {
+        let (mut engine, clock) = engine(&["a@b"]);
+
+        type_text(&mut engine, "a");
+        press(&mut engine, RdevKey::Alt);
+        engine.handle(&MockSource::key_press(RdevKey::KeyL, Some("@")));
+        release(&mut engine, RdevKey::Alt);
+        type_text(&mut engine, "b");
+
+        clock.advance(DELAY);
+        if ALT_TYPES_TEXT {
+            assert_eq!(engine.buffer(), "a@b");
+            assert_eq!(expanded(engine.tick()).snippet.trigger, "a@b");
+        } else {
+            assert_eq!(engine.buffer(), "b");
+            assert!(engine.tick().is_none());
+        }
+    }
+
+    #[test]
+    fn alt_without_a_typed_character_is_always_a_shortcut() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "si");
+        press(&mut engine, RdevKey::Alt);
+        press(&mut engine, RdevKey::LeftArrow);
+        release(&mut engine, RdevKey::Alt);
+        type_text(&mut engine, "g");
+
+        clock.advance(DELAY);
+        assert_eq!(engine.buffer(), "g");
+        assert!(engine.tick().is_none());
+    }
+
+    #[test]
+    fn delete_word_drops_separators_then_the_word() {
+        let mut buffer = TypedBuffer::new();
+        for ch in "say hi, ".chars() {
+            buffer.push(ch);
+        }
+
+        buffer.delete_word();
+        assert_eq!(buffer.text, "say ");
+        assert!(buffer.starts_at_boundary);
+
+        buffer.delete_word();
+        assert_eq!(buffer.text, "");
+        assert!(buffer.starts_at_boundary);
+    }
+
+    #[test]
+    fn ctrl_backspace_deletes_a_word_from_the_buffer() {
+        let (mut engine, clock) = engine(&["sig"]);
+
+        type_text(&mut engine, "sig oops");
+        press(&mut engine, RdevKey::ControlLeft);
+        press(&mut engine, RdevKey::Backspace);
+        release(&mut engine, RdevKey::ControlLeft);
+        assert_eq!(engine.buffer(), "sig ");
+
+        press(&mut engine, RdevKey::Backspace);
+        assert_eq!(engine.buffer(), "sig");
+        clock.advance(DELAY);
+        assert_eq!(expanded(engine.tick()).snippet.trigger, "sig");
+    }
+}
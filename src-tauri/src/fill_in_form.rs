@@ -0,0 +1,208 @@
+use crate::template::FormField;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, WebviewUrl, WebviewWindow, WebviewWindowBuilder, WindowEvent};
+
+/// Label prefix of fill-in form windows. The frontend renders the form
+/// instead of the dashboard in windows whose label starts with it.
+pub const FORM_WINDOW_PREFIX: &str = "snippet-form-";
+
+/// What a form window asks for; fetched by the window with `get_form_request`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FormRequest {
+    pub id: String,
+    pub trigger: String,
+    pub description: Option<String>,
+    pub fields: Vec<FormField>,
+}
+
+struct PendingForm {
+    request: FormRequest,
+    reply: Sender<Option<HashMap<String, String>>>,
+    window: Option<WebviewWindow>,
+    // Set once the form is submitted or cancelled; the values are handed
+    // back when the window is gone so they are not typed into it.
+    answer: Option<Option<HashMap<String, String>>>,
+}
+
+/// Shows the fill-in form for snippets with `{{field:...}}` variables and
+/// hands the values back to the expansion waiting for them. Only one form is
+/// open at a time since expansions pause while it is shown.
+#[derive(Default)]
+pub struct FillInForms {
+    pending: Mutex<Option<PendingForm>>,
+}
+
+impl FillInForms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a form window for `request` and blocks until it is submitted,
+    /// returning the values, or dismissed, returning `None`. Only returns
+    /// once the window has been destroyed.
+    pub fn ask(self: &Arc<Self>, app: &AppHandle, request: FormRequest) -> Option<HashMap<String, String>> {
+        let id = request.id.clone();
+        let (reply, answer) = mpsc::channel();
+        *self.pending.lock().unwrap() = Some(PendingForm {
+            request,
+            reply,
+            window: None,
+            answer: None,
+        });
+
+        let window = WebviewWindowBuilder::new(
+            app,
+            format!("{}{}", FORM_WINDOW_PREFIX, id),
+            WebviewUrl::App("index.html".into()),
+        )
+        .title("Fill in snippet")
+        .inner_size(420.0, 480.0)
+        .center()
+        .always_on_top(true)
+        .focused(true)
+        .build();
+
+        match window {
+            Ok(window) => {
+                // Closing the window without submitting cancels the expansion.
+                let forms = Arc::clone(self);
+                let window_id = id.clone();
+                window.on_window_event(move |event| {
+                    if let WindowEvent::Destroyed = event {
+                        forms.finish(&window_id);
+                    }
+                });
+                let answered = match self.pending.lock().unwrap().as_mut() {
+                    Some(pending) if pending.request.id == id => {
+                        pending.window = Some(window.clone());
+                        pending.answer.is_some()
+                    }
+                    _ => true,
+                };
+                // Answered before the window was even stored.
+                if answered {
+                    self.close(&id, &window);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to open fill-in form: {}", e);
+                self.finish(&id);
+            }
+        }
+
+        answer.recv().ok().flatten()
+    }
+
+    /// The form currently waiting for values, if any.
+    pub fn current(&self) -> Option<FormRequest> {
+        self.pending
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|pending| pending.request.clone())
+    }
+
+    pub fn submit(&self, id: &str, values: HashMap<String, String>) -> Result<(), String> {
+        self.answer(id, Some(values))
+    }
+
+    pub fn cancel(&self, id: &str) -> Result<(), String> {
+        self.answer(id, None)
+    }
+
+    /// Records the answer and closes the window; the expansion waiting for
+    /// it resumes once the window is destroyed.
+    fn answer(&self, id: &str, values: Option<HashMap<String, String>>) -> Result<(), String> {
+        let window = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.as_mut() {
+                Some(form) if form.request.id == id && form.answer.is_none() => {
+                    form.answer = Some(values);
+                    form.window.clone()
+                }
+                _ => return Err(format!("Form '{}' is no longer open", id)),
+            }
+        };
+
+        // Not stored yet: `ask` closes it as soon as it is.
+        if let Some(window) = window {
+            self.close(id, &window);
+        }
+        Ok(())
+    }
+
+    fn close(&self, id: &str, window: &WebviewWindow) {
+        if let Err(e) = window.close().or_else(|_| window.destroy()) {
+            eprintln!("Failed to close fill-in form: {}", e);
+            self.finish(id);
+        }
+    }
+
+    /// Hands the answer, or `None` if there was none, to the waiting expansion.
+    fn finish(&self, id: &str) {
+        let form = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.take() {
+                Some(form) if form.request.id == id => form,
+                other => {
+                    *pending = other;
+                    return;
+                }
+            }
+        };
+        let _ = form.reply.send(form.answer.flatten());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{Receiver, TryRecvError};
+
+    fn open(forms: &FillInForms, id: &str) -> Receiver<Option<HashMap<String, String>>> {
+        let (reply, answer) = mpsc::channel();
+        *forms.pending.lock().unwrap() = Some(PendingForm {
+            request: FormRequest {
+                id: id.to_string(),
+                trigger: "sig".to_string(),
+                description: None,
+                fields: Vec::new(),
+            },
+            reply,
+            window: None,
+            answer: None,
+        });
+        answer
+    }
+
+    #[test]
+    fn submitted_values_wait_for_the_window_to_be_destroyed() {
+        let forms = FillInForms::new();
+        let answer = open(&forms, "a");
+        let values = HashMap::from([("name".to_string(), "Ada".to_string())]);
+
+        forms.submit("a", values.clone()).unwrap();
+        assert_eq!(answer.try_recv(), Err(TryRecvError::Empty));
+        assert!(forms.current().is_some());
+        assert!(forms.cancel("a").is_err());
+
+        forms.finish("a");
+        assert_eq!(answer.try_recv(), Ok(Some(values)));
+        assert!(forms.current().is_none());
+    }
+
+    #[test]
+    fn destroying_an_unanswered_form_cancels_it() {
+        let forms = FillInForms::new();
+        let answer = open(&forms, "a");
+
+        forms.finish("b");
+        assert_eq!(answer.try_recv(), Err(TryRecvError::Empty));
+        forms.finish("a");
+        assert_eq!(answer.try_recv(), Ok(None));
+        assert!(forms.submit("a", HashMap::new()).is_err());
+    }
+}
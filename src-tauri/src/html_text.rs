@@ -0,0 +1,210 @@
+/// Renders HTML snippet content as readable plain text for applications that
+/// only accept the text clipboard flavour. Block elements become line breaks,
+/// list items get a `- ` bullet, entities are decoded and source whitespace is
+/// collapsed the way a browser would.
+pub fn html_to_plain_text(html: &str) -> String {
+    let mut output = String::new();
+    let mut rest = html;
+    let mut pre_depth = 0usize;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        if starts_tag(rest) {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = rest[1..end].trim_end_matches('>');
+            rest = &rest[end..];
+
+            let closing = tag.starts_with('/');
+            let name: String = tag
+                .trim_start_matches('/')
+                .chars()
+                .take_while(|ch| ch.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase();
+
+            match name.as_str() {
+                "script" | "style" if !closing => {
+                    let close = format!("</{}", name);
+                    rest = match rest.to_ascii_lowercase().find(&close) {
+                        Some(start) => &rest[start..],
+                        None => "",
+                    };
+                }
+                "br" => line_break(&mut output),
+                "li" if !closing => {
+                    block_break(&mut output);
+                    output.push_str("- ");
+                }
+                "pre" => {
+                    block_break(&mut output);
+                    pre_depth = if closing { pre_depth.saturating_sub(1) } else { pre_depth + 1 };
+                }
+                "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "li"
+                | "table" | "tr" | "blockquote" | "hr" => block_break(&mut output),
+                "td" | "th" if closing => output.push(' '),
+                _ => {}
+            }
+            continue;
+        }
+
+        // Text runs to the next '<'; a '<' that opens no tag is text itself.
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first..].find('<').map_or(rest.len(), |end| end + first);
+        push_text(&mut output, &decode_entities(&rest[..end]), pre_depth > 0);
+        rest = &rest[end..];
+    }
+
+    let mut text = String::new();
+    for line in output.lines().map(str::trim_end) {
+        if line.is_empty() && text.ends_with("\n\n") {
+            continue;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.trim().to_string()
+}
+
+/// Whether `text` starts with a tag, like a browser decides it: `<` followed
+/// by a letter, `/` or `!`. In `a < b` the `<` is just a character.
+fn starts_tag(text: &str) -> bool {
+    text.strip_prefix('<')
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|next| next.is_ascii_alphabetic() || next == '/' || next == '!')
+}
+
+fn push_text(output: &mut String, text: &str, preformatted: bool) {
+    if preformatted {
+        output.push_str(text);
+        return;
+    }
+
+    for ch in text.chars() {
+        if ch.is_whitespace() {
+            if !output.is_empty() && !output.ends_with([' ', '\n']) {
+                output.push(' ');
+            }
+        } else {
+            output.push(ch);
+        }
+    }
+}
+
+fn line_break(output: &mut String) {
+    let trimmed = output.trim_end_matches(' ').len();
+    output.truncate(trimmed);
+    output.push('\n');
+}
+
+fn block_break(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        line_break(output);
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|ch| (ch, end + 2)));
+
+        match entity {
+            Some((ch, length)) => {
+                decoded.push(ch);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_become_bulleted_lines() {
+        let html = "<p>Todo:</p><ul>\n  <li>milk</li>\n  <li>eggs</li>\n</ul><p>Done</p>";
+        assert_eq!(html_to_plain_text(html), "Todo:\n- milk\n- eggs\nDone");
+    }
+
+    #[test]
+    fn br_breaks_lines_and_source_whitespace_collapses() {
+        assert_eq!(html_to_plain_text("Kind   regards,<br>\n  Jane<br/>Doe"), "Kind regards,\nJane\nDoe");
+    }
+
+    #[test]
+    fn pre_keeps_its_whitespace() {
+        let html = "<p>Run:</p><pre>cargo  test\n  --workspace</pre><p>then   push</p>";
+        assert_eq!(html_to_plain_text(html), "Run:\ncargo  test\n  --workspace\nthen push");
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(html_to_plain_text("a &amp; b &lt;c&gt; &quot;d&quot; &apos;e&apos;"), "a & b <c> \"d\" 'e'");
+        assert_eq!(html_to_plain_text("&#65;&#x42;&#X43;&nbsp;&#x1F600;"), "ABC \u{1F600}");
+    }
+
+    #[test]
+    fn unknown_entities_and_stray_ampersands_stay() {
+        assert_eq!(html_to_plain_text("Q&A &bogus; &#xZZ; &"), "Q&A &bogus; &#xZZ; &");
+    }
+
+    #[test]
+    fn stray_angle_brackets_do_not_lose_text() {
+        assert_eq!(html_to_plain_text("1 > 0"), "1 > 0");
+        assert_eq!(html_to_plain_text("a < b <= c"), "a < b <= c");
+        assert_eq!(html_to_plain_text("<b>bold</b> and <"), "bold and <");
+        assert_eq!(html_to_plain_text("é<3"), "é<3");
+    }
+
+    #[test]
+    fn comments_scripts_and_styles_are_dropped() {
+        let html = "<style>p { color: red }</style><!-- note --><p>Hi</p><script>alert(1)</script>";
+        assert_eq!(html_to_plain_text(html), "Hi");
+    }
+
+    #[test]
+    fn table_cells_are_separated() {
+        assert_eq!(
+            html_to_plain_text("<table><tr><td>a</td><td>b</td></tr><tr><td>c</td></tr></table>"),
+            "a b\nc"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use rdev::Event;
+#[cfg(test)]
+use crate::clipboard_handler::MockClipboard;
+#[cfg(test)]
+use rdev::{EventType, Key as RdevKey};
+#[cfg(test)]
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+#[cfg(test)]
+use std::time::SystemTime;
+
+/// Delivers global keyboard and mouse events to the expansion engine.
+pub trait KeySource: Send + Sync {
+    /// Calls `callback` for every event until the source stops. Blocks the
+    /// calling thread.
+    fn listen(&self, callback: Box<dyn FnMut(Event) + Send>) -> Result<(), String>;
+}
+
+/// Sends synthesized keys and text to the focused application.
+pub trait KeyInjector: Send {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<(), String>;
+    fn text(&mut self, text: &str) -> Result<(), String>;
+}
+
+/// Global hook through rdev (X11, Windows, macOS).
+pub struct RdevSource;
+
+impl KeySource for RdevSource {
+    fn listen(&self, callback: Box<dyn FnMut(Event) + Send>) -> Result<(), String> {
+        rdev::listen(callback).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Synthesized input through enigo.
+pub struct EnigoInjector {
+    enigo: Enigo,
+}
+
+impl EnigoInjector {
+    pub fn new() -> Result<Self, String> {
+        let enigo = Enigo::new(&Settings::default()).map_err(|e| format!("Failed to init enigo: {:?}", e))?;
+        Ok(Self { enigo })
+    }
+}
+
+impl KeyInjector for EnigoInjector {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<(), String> {
+        self.enigo.key(key, direction).map_err(|e| format!("{:?}", e))
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), String> {
+        self.enigo.text(text).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Delivers events sent through a channel, for driving the handler without
+/// a display. Listening ends once every sender is dropped.
+#[cfg(test)]
+pub struct MockSource {
+    events: Mutex<Option<Receiver<Event>>>,
+}
+
+#[cfg(test)]
+impl MockSource {
+    /// The source and the sender feeding it, which may keep sending after
+    /// the source has been handed to a `KeyboardHandler`.
+    pub fn new() -> (Self, Sender<Event>) {
+        let (sender, receiver) = mpsc::channel();
+        let source = Self {
+            events: Mutex::new(Some(receiver)),
+        };
+        (source, sender)
+    }
+
+    /// A key press as rdev reports it, `name` being the character produced.
+    pub fn key_press(key: RdevKey, name: Option<&str>) -> Event {
+        Event {
+            time: SystemTime::now(),
+            name: name.map(str::to_string),
+            event_type: EventType::KeyPress(key),
+        }
+    }
+
+    pub fn key_release(key: RdevKey) -> Event {
+        Event {
+            time: SystemTime::now(),
+            name: None,
+            event_type: EventType::KeyRelease(key),
+        }
+    }
+}
+
+#[cfg(test)]
+impl KeySource for MockSource {
+    fn listen(&self, mut callback: Box<dyn FnMut(Event) + Send>) -> Result<(), String> {
+        let events = self
+            .events
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| "Mock source is already listening".to_string())?;
+        for event in events {
+            callback(event);
+        }
+        Ok(())
+    }
+}
+
+/// Something a `MockInjector` was asked to send.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Injected {
+    Key(Key, Direction),
+    Text(String),
+}
+
+/// A single-line text field that `MockInjector` types into.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockDocument {
+    pub text: Vec<char>,
+    /// Caret position in characters.
+    pub caret: usize,
+    /// Everything injected, in order.
+    pub injected: Vec<Injected>,
+}
+
+#[cfg(test)]
+impl MockDocument {
+    pub fn contents(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Inserts `text` at the caret, as the user typing it would.
+    pub fn insert(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.text.insert(self.caret, ch);
+            self.caret += 1;
+        }
+    }
+}
+
+/// Records injected input and applies Backspace, the arrow keys and typed
+/// text to a `MockDocument`, so expansions can be checked headlessly.
+/// Ctrl+V or Meta+V pastes the text of the clipboard given to
+/// `with_clipboard`; other chords are only recorded.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub struct MockInjector {
+    document: Arc<Mutex<MockDocument>>,
+    clipboard: Option<MockClipboard>,
+    held: Vec<Key>,
+}
+
+#[cfg(test)]
+impl MockInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clipboard(clipboard: MockClipboard) -> Self {
+        Self {
+            clipboard: Some(clipboard),
+            ..Self::default()
+        }
+    }
+
+    fn pastes(&self, key: Key) -> bool {
+        key == Key::Unicode('v') && self.held.iter().any(|held| matches!(held, Key::Control | Key::Meta))
+    }
+
+    /// Shared handle to the document, still usable after the injector has
+    /// been handed to a `KeyboardHandler`.
+    pub fn document(&self) -> Arc<Mutex<MockDocument>> {
+        Arc::clone(&self.document)
+    }
+}
+
+#[cfg(test)]
+impl KeyInjector for MockInjector {
+    fn key(&mut self, key: Key, direction: Direction) -> Result<(), String> {
+        let mut document = self.document.lock().unwrap();
+        document.injected.push(Injected::Key(key, direction));
+        match direction {
+            Direction::Release => {
+                self.held.retain(|held| *held != key);
+                return Ok(());
+            }
+            Direction::Press => self.held.push(key),
+            Direction::Click => {}
+        }
+
+        match key {
+            _ if self.pastes(key) => {
+                let text = self
+                    .clipboard
+                    .as_ref()
+                    .and_then(|clipboard| clipboard.contents().lock().unwrap().text.clone());
+                document.insert(&text.unwrap_or_default());
+            }
+            Key::Backspace if document.caret > 0 => {
+                document.caret -= 1;
+                let caret = document.caret;
+                document.text.remove(caret);
+            }
+            Key::LeftArrow => document.caret = document.caret.saturating_sub(1),
+            Key::RightArrow => document.caret = (document.caret + 1).min(document.text.len()),
+            Key::Return => document.insert("\n"),
+            Key::Tab => document.insert("\t"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), String> {
+        let mut document = self.document.lock().unwrap();
+        document.injected.push(Injected::Text(text.to_string()));
+        document.insert(text);
+        Ok(())
+    }
+}
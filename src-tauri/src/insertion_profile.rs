@@ -0,0 +1,257 @@
+use crate::snippet::InsertMethod;
+use enigo::Key;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Paste shortcut used when no profile matches the focused application.
+#[cfg(target_os = "macos")]
+pub const DEFAULT_PASTE_CHORD: &str = "meta+v";
+#[cfg(not(target_os = "macos"))]
+pub const DEFAULT_PASTE_CHORD: &str = "ctrl+v";
+
+/// How often `wait_for_focus` checks the focused window.
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How expansions are inserted into one application, for example a terminal
+/// that pastes with Ctrl+Shift+V or a remote desktop that needs slower typing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsertionProfile {
+    pub id: String,
+    pub app_class: String,  // Window class (X11 WM_CLASS) of the application, compared case-insensitively
+    pub paste_chord: String,  // Keys joined by '+', e.g. "ctrl+shift+v"
+    pub insert_method: Option<InsertMethod>,  // Overrides Snippet::insert_method when set
+    pub key_delay_ms: Option<u64>,  // Wait between synthesized Backspace and arrow keys
+    pub paste_delay_ms: Option<u64>,  // Wait between the keys of the paste chord
+    pub clipboard_restore_delay_ms: Option<u64>,  // Overrides SnippetSettings::clipboard_restore_delay_ms when set
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl InsertionProfile {
+    pub fn new(
+        app_class: String,
+        paste_chord: String,
+        insert_method: Option<InsertMethod>,
+        key_delay_ms: Option<u64>,
+        paste_delay_ms: Option<u64>,
+        clipboard_restore_delay_ms: Option<u64>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            app_class,
+            paste_chord,
+            insert_method,
+            key_delay_ms,
+            paste_delay_ms,
+            clipboard_restore_delay_ms,
+            created_at: timestamp,
+            updated_at: timestamp,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        app_class: String,
+        paste_chord: String,
+        insert_method: Option<InsertMethod>,
+        key_delay_ms: Option<u64>,
+        paste_delay_ms: Option<u64>,
+        clipboard_restore_delay_ms: Option<u64>,
+    ) {
+        self.app_class = app_class;
+        self.paste_chord = paste_chord;
+        self.insert_method = insert_method;
+        self.key_delay_ms = key_delay_ms;
+        self.paste_delay_ms = paste_delay_ms;
+        self.clipboard_restore_delay_ms = clipboard_restore_delay_ms;
+        self.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+    }
+
+    /// Checks the profile before it is saved.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.app_class.trim().is_empty() {
+            return Err("Application class must not be empty".to_string());
+        }
+        parse_chord(&self.paste_chord).map(|_| ())
+    }
+
+    /// Whether the profile applies to a window with one of `window_classes`.
+    pub fn matches(&self, window_classes: &[String]) -> bool {
+        window_classes
+            .iter()
+            .any(|class| class.eq_ignore_ascii_case(self.app_class.trim()))
+    }
+}
+
+/// Splits a chord such as `ctrl+shift+v` into the modifiers to hold and the
+/// key to press while they are held.
+pub fn parse_chord(chord: &str) -> Result<(Vec<Key>, Key), String> {
+    let mut names: Vec<String> = chord
+        .split('+')
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    let key_name = names.pop().filter(|name| !name.is_empty());
+    let Some(key_name) = key_name else {
+        return Err(format!("Invalid paste chord '{}'", chord));
+    };
+
+    let modifiers = names
+        .iter()
+        .map(|name| match name.as_str() {
+            "ctrl" | "control" => Ok(Key::Control),
+            "shift" => Ok(Key::Shift),
+            "alt" | "option" => Ok(Key::Alt),
+            "meta" | "cmd" | "command" | "super" | "win" => Ok(Key::Meta),
+            _ => Err(format!("Unknown modifier '{}' in paste chord '{}'", name, chord)),
+        })
+        .collect::<Result<Vec<Key>, String>>()?;
+
+    let mut chars = key_name.chars();
+    let key = match (key_name.as_str(), chars.next(), chars.next()) {
+        #[cfg(not(target_os = "macos"))]
+        ("insert" | "ins", _, _) => Key::Insert,
+        (_, Some(ch), None) if ch.is_ascii_alphanumeric() => Key::Unicode(ch),
+        _ => return Err(format!("Unknown key '{}' in paste chord '{}'", key_name, chord)),
+    };
+
+    Ok((modifiers, key))
+}
+
+/// Runs a command, returning its output when it succeeds.
+#[cfg(target_os = "linux")]
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// X11 id of the focused window, e.g. `0x3a00007`. Only X11 is supported;
+/// elsewhere, or when `xprop` is unavailable, this is `None`.
+#[cfg(target_os = "linux")]
+pub fn focused_window() -> Option<String> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let active = run("xprop", &["-root", "_NET_ACTIVE_WINDOW"])?;
+    active
+        .split_whitespace()
+        .last()
+        .filter(|id| id.starts_with("0x") && *id != "0x0")
+        .map(str::to_string)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn focused_window() -> Option<String> {
+    None
+}
+
+/// Asks the window manager to focus `window`, an id from `focused_window`.
+/// Needs `xdotool`; returns whether the request was made.
+#[cfg(target_os = "linux")]
+fn activate_window(window: &str) -> bool {
+    let Ok(id) = u64::from_str_radix(window.trim_start_matches("0x"), 16) else {
+        return false;
+    };
+    run("xdotool", &["windowactivate", &id.to_string()]).is_some()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn activate_window(_window: &str) -> bool {
+    false
+}
+
+/// Waits up to `timeout` for `window` to be focused again, re-activating it
+/// halfway through if it has not come back on its own. Returns whether it
+/// has the focus.
+pub fn wait_for_focus(window: &str, timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut activated = false;
+    loop {
+        if focused_window().as_deref() == Some(window) {
+            return true;
+        }
+        let waited = start.elapsed();
+        if waited >= timeout {
+            return false;
+        }
+        if !activated && waited >= timeout / 2 {
+            activated = true;
+            activate_window(window);
+        }
+        thread::sleep(FOCUS_POLL_INTERVAL);
+    }
+}
+
+/// Window classes of the focused application, most specific first. Only X11
+/// is supported; elsewhere, or when `xprop` is unavailable, the list is empty
+/// and the default insertion settings apply.
+#[cfg(target_os = "linux")]
+pub fn focused_window_classes() -> Vec<String> {
+    let Some(window_id) = focused_window() else {
+        return Vec::new();
+    };
+
+    // WM_CLASS(STRING) = "gnome-terminal-server", "Gnome-terminal-server"
+    let Some(class) = run("xprop", &["-id", &window_id, "WM_CLASS"]) else {
+        return Vec::new();
+    };
+    class
+        .split_once('=')
+        .map(|(_, values)| {
+            values
+                .split(',')
+                .map(|value| value.trim().trim_matches('"').to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn focused_window_classes() -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modifiers_and_key() {
+        assert_eq!(
+            parse_chord("ctrl+shift+v"),
+            Ok((vec![Key::Control, Key::Shift], Key::Unicode('v')))
+        );
+        assert_eq!(parse_chord(" Cmd + V "), Ok((vec![Key::Meta], Key::Unicode('v'))));
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn parses_shift_insert() {
+        assert_eq!(parse_chord("shift+insert"), Ok((vec![Key::Shift], Key::Insert)));
+    }
+
+    #[test]
+    fn rejects_unknown_modifiers_and_keys() {
+        assert_eq!(
+            parse_chord("hyper+v"),
+            Err("Unknown modifier 'hyper' in paste chord 'hyper+v'".to_string())
+        );
+        assert!(parse_chord("ctrl+enter").is_err());
+    }
+
+    #[test]
+    fn rejects_an_empty_key() {
+        assert_eq!(parse_chord("ctrl+"), Err("Invalid paste chord 'ctrl+'".to_string()));
+        assert!(parse_chord("").is_err());
+    }
+}
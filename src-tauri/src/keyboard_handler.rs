@@ -1,353 +1,865 @@
-use crate::clipboard_handler::{ClipboardHandler};
+use crate::clipboard_handler::{ClipboardBackend, ClipboardHandler, ClipboardSnapshot};
 use crate::config::Database;
-use enigo::{Enigo, Key, Keyboard, Settings};
-use rdev::{listen, Event, EventType, Key as RdevKey};
-use std::sync::{Arc, Mutex};
+use crate::expansion_engine::{Action, Expansion, ExpansionEngine, Expected, LastExpansion, SystemClock};
+use crate::fill_in_form::{FillInForms, FormRequest};
+use crate::html_text::html_to_plain_text;
+use crate::input_backend::{EnigoInjector, KeyInjector, KeySource, RdevSource};
+use crate::insertion_profile::{focused_window, focused_window_classes, parse_chord, wait_for_focus, DEFAULT_PASTE_CHORD};
+use crate::snippet::{split_cursor_marker, InsertMethod, Snippet};
+use crate::snippet_settings::SnippetSettings;
+use crate::template::{self, FormField, RenderContext, Token};
+use enigo::{Direction, Key};
+use rdev::{Event, EventType, Key as RdevKey};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
 
-const MAX_TRIGGER_LENGTH: usize = 100;
+/// Event sent to the frontend when user input stopped an expansion.
+pub const EXPANSION_ABORTED_EVENT: &str = "expansion-aborted";
+
+/// How long the window a fill-in form was opened from may take to get the
+/// focus back once the form is gone.
+const FOCUS_RETURN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+struct ExpansionAborted {
+    trigger: String,
+    /// Whether it was the undo of an expansion that was stopped.
+    undo: bool,
+}
+
+/// How to insert one expansion: the global settings with the focused
+/// application's `InsertionProfile` applied on top.
+#[derive(Debug)]
+struct InsertionSettings {
+    paste_chord: String,
+    /// Overrides `Snippet::insert_method` when set.
+    insert_method: Option<InsertMethod>,
+    paste_threshold_chars: u64,
+    pre_expansion_delay_ms: u64,
+    key_delay_ms: u64,
+    post_delete_delay_ms: u64,
+    clipboard_settle_delay_ms: u64,
+    paste_delay_ms: u64,
+    clipboard_restore_delay_ms: u64,
+    post_expansion_delay_ms: u64,
+}
 
 pub struct KeyboardHandler {
-    buffer: Arc<Mutex<String>>,
-    last_key_time: Arc<Mutex<Instant>>,
+    engine: Arc<Mutex<ExpansionEngine>>,
     db: Arc<Database>,
-    clipboard: Arc<Mutex<ClipboardHandler>>,
-    enigo: Arc<Mutex<Enigo>>,
+    clipboard: Arc<Mutex<Box<dyn ClipboardBackend>>>,
+    source: Arc<dyn KeySource>,
+    injector: Arc<Mutex<Box<dyn KeyInjector>>>,
+    /// Wakes the timer worker whenever the engine's deadline may have moved.
+    timer: Arc<Mutex<Option<Sender<()>>>>,
+    /// Set once the app is running; used to notify the frontend and open
+    /// fill-in forms.
+    app: Arc<OnceLock<AppHandle>>,
+    forms: Arc<FillInForms>,
 }
 
 impl KeyboardHandler {
     pub fn new(db: Arc<Database>) -> Self {
+        let injector = EnigoInjector::new().expect("Failed to init key injector");
+        let clipboard = ClipboardHandler::new().expect("Failed to init clipboard");
+        Self::with_backends(db, Arc::new(RdevSource), Box::new(injector), Box::new(clipboard))
+    }
+
+    /// Builds a handler that reads events from `source`, types through
+    /// `injector` and pastes through `clipboard` instead of the system hooks.
+    pub fn with_backends(
+        db: Arc<Database>,
+        source: Arc<dyn KeySource>,
+        injector: Box<dyn KeyInjector>,
+        clipboard: Box<dyn ClipboardBackend>,
+    ) -> Self {
+        let snippets = db.get_all_snippets().unwrap_or_else(|e| {
+            eprintln!("Failed to load snippets: {}", e);
+            Vec::new()
+        });
+        let settings = db.get_snippet_settings().unwrap_or_else(|e| {
+            eprintln!("Failed to load snippet settings, using defaults: {}", e);
+            SnippetSettings::new("1".to_string(), 200)
+        });
+
         Self {
-            buffer: Arc::new(Mutex::new(String::new())),
-            last_key_time: Arc::new(Mutex::new(Instant::now())),
+            engine: Arc::new(Mutex::new(ExpansionEngine::new(snippets, settings, Arc::new(SystemClock)))),
             db,
-            clipboard: Arc::new(Mutex::new(ClipboardHandler::new().expect("Failed to init clipboard"))),
-            enigo: Arc::new(Mutex::new(Enigo::new(&Settings::default()).unwrap())),
+            clipboard: Arc::new(Mutex::new(clipboard)),
+            source,
+            injector: Arc::new(Mutex::new(injector)),
+            timer: Arc::new(Mutex::new(None)),
+            app: Arc::new(OnceLock::new()),
+            forms: Arc::new(FillInForms::new()),
         }
     }
 
+    /// Rebuilds the trigger index from the database.
+    pub fn reload_snippets(&self) -> Result<(), String> {
+        let snippets = self.db.get_all_snippets()?;
+        self.engine.lock().unwrap().reload_snippets(snippets);
+        Ok(())
+    }
+
+    pub fn upsert_snippet(&self, snippet: Snippet) {
+        self.engine.lock().unwrap().upsert_snippet(snippet);
+    }
+
+    pub fn remove_snippet(&self, id: &str) {
+        self.engine.lock().unwrap().remove_snippet(id);
+    }
+
+    /// Replaces the settings the engine works with; called after they are
+    /// saved so key handling never has to query the database.
+    pub fn update_settings(&self, settings: SnippetSettings) {
+        self.engine.lock().unwrap().set_settings(settings);
+    }
+
+    pub fn forms(&self) -> &FillInForms {
+        &self.forms
+    }
+
+    pub fn set_app_handle(&self, app: AppHandle) {
+        let _ = self.app.set(app);
+    }
+
     pub fn start_listening(self: Arc<Self>) {
+        let (sender, receiver) = mpsc::channel();
+        *self.timer.lock().unwrap() = Some(sender);
+        let timer = self.clone_for_timer();
+        thread::spawn(move || {
+            timer.run_timer(receiver);
+        });
+
+        let source = Arc::clone(&self.source);
         thread::spawn(move || {
-            if let Err(error) = listen(move |event| {
+            if let Err(error) = source.listen(Box::new(move |event| {
                 self.handle_event(event);
-            }) {
-                eprintln!("Error in keyboard listener: {:?}", error);
+            })) {
+                eprintln!("Error in keyboard listener: {}", error);
             }
         });
     }
 
     fn handle_event(&self, event: Event) {
-        match event.event_type {
-            EventType::KeyRelease(key) => {
-                self.handle_key_release(key);
+        let is_key_press = matches!(event.event_type, EventType::KeyPress(_));
+        let action = self.engine.lock().unwrap().handle(&event);
+
+        match action {
+            Some(action) => {
+                let handler = self.clone_for_timer();
+                thread::spawn(move || {
+                    handler.perform(action);
+                });
             }
-            EventType::ButtonPress(_) => {
-                self.buffer.lock().unwrap().clear();
+            None if is_key_press => self.schedule_tick(),
+            None => {}
+        }
+    }
+
+    /// Tells the timer worker to pick up the engine's new deadline.
+    fn schedule_tick(&self) {
+        if let Some(timer) = &*self.timer.lock().unwrap() {
+            let _ = timer.send(());
+        }
+    }
+
+    /// Body of the single timer thread. It sleeps until the engine's deadline
+    /// and lets the engine expand the buffer if no key moved the deadline in
+    /// the meantime; every key press wakes it to re-read the deadline.
+    fn run_timer(&self, wakeups: Receiver<()>) {
+        loop {
+            let deadline = self.engine.lock().unwrap().deadline();
+            let woken = match deadline {
+                Some(deadline) => wakeups.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => wakeups.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match woken {
+                Ok(()) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    let action = self.engine.lock().unwrap().tick();
+                    if let Some(action) = action {
+                        self.perform(action);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
             }
-            _ => {}
-        }
-    }
-
-    fn key_to_char(&self, key: &RdevKey) -> Option<char> {
-        match key {
-            RdevKey::KeyA => Some('a'),
-            RdevKey::KeyB => Some('b'),
-            RdevKey::KeyC => Some('c'),
-            RdevKey::KeyD => Some('d'),
-            RdevKey::KeyE => Some('e'),
-            RdevKey::KeyF => Some('f'),
-            RdevKey::KeyG => Some('g'),
-            RdevKey::KeyH => Some('h'),
-            RdevKey::KeyI => Some('i'),
-            RdevKey::KeyJ => Some('j'),
-            RdevKey::KeyK => Some('k'),
-            RdevKey::KeyL => Some('l'),
-            RdevKey::KeyM => Some('m'),
-            RdevKey::KeyN => Some('n'),
-            RdevKey::KeyO => Some('o'),
-            RdevKey::KeyP => Some('p'),
-            RdevKey::KeyQ => Some('q'),
-            RdevKey::KeyR => Some('r'),
-            RdevKey::KeyS => Some('s'),
-            RdevKey::KeyT => Some('t'),
-            RdevKey::KeyU => Some('u'),
-            RdevKey::KeyV => Some('v'),
-            RdevKey::KeyW => Some('w'),
-            RdevKey::KeyX => Some('x'),
-            RdevKey::KeyY => Some('y'),
-            RdevKey::KeyZ => Some('z'),
-            RdevKey::Num0 => Some('0'),
-            RdevKey::Num1 => Some('1'),
-            RdevKey::Num2 => Some('2'),
-            RdevKey::Num3 => Some('3'),
-            RdevKey::Num4 => Some('4'),
-            RdevKey::Num5 => Some('5'),
-            RdevKey::Num6 => Some('6'),
-            RdevKey::Num7 => Some('7'),
-            RdevKey::Num8 => Some('8'),
-            RdevKey::Num9 => Some('9'),
-            RdevKey::Minus => Some('-'),
-            RdevKey::Equal => Some('='),
-            RdevKey::LeftBracket => Some('['),
-            RdevKey::RightBracket => Some(']'),
-            RdevKey::SemiColon => Some(';'),
-            RdevKey::Quote => Some('\''),
-            RdevKey::BackSlash => Some('\\'),
-            RdevKey::Comma => Some(','),
-            RdevKey::Dot => Some('.'),
-            RdevKey::Slash => Some('/'),
-            RdevKey::BackQuote => Some('`'),
-            _ => None,
-        }
-    }
-
-    fn handle_key_release(&self, key: RdevKey) {
-        let mut buffer = self.buffer.lock().unwrap();
-        let mut last_key_time = self.last_key_time.lock().unwrap();
-        let now = Instant::now();
-
-        let time_delay_ms = match self.db.get_snippet_settings() {
-            Ok(delay) => delay.time_delay_ms as u64,
-            Err(_) => 200,
-        };
-        let buffer_timeout = Duration::from_millis(time_delay_ms);
-
-        if buffer.len() > 0 && now.duration_since(*last_key_time) >= buffer_timeout {
-            let buffer_str = buffer.clone();
-            drop(buffer);
-            drop(last_key_time);
-            
-            self.check_and_replace_snippet(&buffer_str);
-            
-            self.buffer.lock().unwrap().clear();
-            return;
         }
+    }
 
-        *last_key_time = now;
-
-        match key {
-            RdevKey::KeyA | RdevKey::KeyB | RdevKey::KeyC | RdevKey::KeyD | RdevKey::KeyE
-            | RdevKey::KeyF | RdevKey::KeyG | RdevKey::KeyH | RdevKey::KeyI | RdevKey::KeyJ
-            | RdevKey::KeyK | RdevKey::KeyL | RdevKey::KeyM | RdevKey::KeyN | RdevKey::KeyO
-            | RdevKey::KeyP | RdevKey::KeyQ | RdevKey::KeyR | RdevKey::KeyS | RdevKey::KeyT
-            | RdevKey::KeyU | RdevKey::KeyV | RdevKey::KeyW | RdevKey::KeyX | RdevKey::KeyY
-            | RdevKey::KeyZ | RdevKey::Num0 | RdevKey::Num1 | RdevKey::Num2 | RdevKey::Num3
-            | RdevKey::Num4 | RdevKey::Num5 | RdevKey::Num6 | RdevKey::Num7 | RdevKey::Num8
-            | RdevKey::Num9 | RdevKey::Minus | RdevKey::Equal | RdevKey::LeftBracket 
-            | RdevKey::RightBracket | RdevKey::SemiColon | RdevKey::Quote
-            | RdevKey::BackSlash | RdevKey::Comma | RdevKey::Dot | RdevKey::Slash
-            | RdevKey::BackQuote => {
-                if let Some(ch) = self.key_to_char(&key) {
-                    buffer.push(ch);
-                    *last_key_time = now;
+    /// Injects the input for an engine action. The engine ignores events
+    /// until it is told the action is finished, which always happens, even
+    /// when injection fails half way.
+    fn perform(&self, action: Action) {
+        match action {
+            Action::Expand(expansion) => {
+                let last_expansion = self.replace_trigger_with_content(&expansion);
+                if self.engine.lock().unwrap().finish_expansion(last_expansion) {
+                    self.report_aborted(&expansion.snippet.trigger, false);
+                }
+            }
+            Action::Undo(last_expansion) => {
+                self.undo_expansion(&last_expansion);
+                if self.engine.lock().unwrap().finish_undo(&last_expansion) {
+                    self.report_aborted(&last_expansion.typed, true);
                 }
             }
+        }
+    }
+
+    fn clone_for_timer(&self) -> Self {
+        Self {
+            engine: Arc::clone(&self.engine),
+            db: Arc::clone(&self.db),
+            clipboard: Arc::clone(&self.clipboard),
+            source: Arc::clone(&self.source),
+            injector: Arc::clone(&self.injector),
+            timer: Arc::clone(&self.timer),
+            app: Arc::clone(&self.app),
+            forms: Arc::clone(&self.forms),
+        }
+    }
 
-            RdevKey::Backspace => {
-                buffer.pop();
-                *last_key_time = now;
+    /// Carries out an expansion, returning what Backspace needs to undo it
+    /// when that is possible.
+    fn replace_trigger_with_content(&self, expansion: &Expansion) -> Option<LastExpansion> {
+        let snippet = &expansion.snippet;
+        let fields = self.form_fields(snippet);
+        let values = if fields.is_empty() {
+            HashMap::new()
+        } else {
+            self.fill_in_form(snippet, fields)?
+        };
+
+        let content = self.render_template(&snippet.trigger, &snippet.content, snippet.is_html, &values);
+        let content = expansion.case_style.apply(&content, snippet.is_html);
+        let (content, chars_after_cursor) = split_cursor_marker(&content);
+        let content = content.as_str();
+        let snippet_id = snippet.id.as_str();
+        let trailing = expansion.trailing;
+
+        let insertion = self.insertion_settings();
+        let insert_method = insertion.insert_method.unwrap_or(snippet.insert_method);
+
+        thread::sleep(Duration::from_millis(insertion.pre_expansion_delay_ms));
+
+        {
+            let mut injector = self.injector.lock().unwrap();
+            for i in 0..expansion.delete_count {
+                if self.aborted() {
+                    return None;
+                }
+                if let Err(e) = self.inject_key(&mut **injector, Key::Backspace, Direction::Click) {
+                    eprintln!("Failed to send backspace {}: {}", i, e);
+                    return None;
+                }
+                thread::sleep(Duration::from_millis(insertion.key_delay_ms));
             }
+        }
 
-            RdevKey::Return | RdevKey::Tab | RdevKey::Escape | RdevKey::LeftArrow
-            | RdevKey::RightArrow | RdevKey::UpArrow | RdevKey::DownArrow | RdevKey::Home
-            | RdevKey::End | RdevKey::PageUp | RdevKey::PageDown | RdevKey::Delete => {
-                buffer.clear();
-                return;
+        thread::sleep(Duration::from_millis(insertion.post_delete_delay_ms));
+        if self.aborted() {
+            return None;
+        }
+
+        let inserted = if snippet.is_html {
+            let plain_text = match &snippet.plain_text {
+                Some(plain_text) => {
+                    let plain_text = self.render_template(&snippet.trigger, plain_text, false, &values);
+                    split_cursor_marker(&expansion.case_style.apply(&plain_text, false)).0
+                }
+                None => html_to_plain_text(content),
+            };
+            self.paste_via_clipboard(|clipboard| clipboard.set_html(content, &plain_text), &insertion)
+        } else if insert_method.pastes(content, insertion.paste_threshold_chars) {
+            self.paste_via_clipboard(|clipboard| clipboard.set_text(content), &insertion)
+        } else {
+            match self.inject_text(&mut **self.injector.lock().unwrap(), content) {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Failed to insert text: {}", e);
+                    false
+                }
             }
+        };
+        if !inserted || self.aborted() {
+            return None;
+        }
 
-            _ => {
-                return;
+        {
+            let mut injector = self.injector.lock().unwrap();
+            self.type_trailing(&mut **injector, trailing);
+            // In HTML the count after the marker includes markup, so the
+            // caret is only placed in plain text.
+            if chars_after_cursor > 0 && !snippet.is_html {
+                self.move_cursor_back(
+                    &mut **injector,
+                    chars_after_cursor + trailing.map_or(0, |_| 1),
+                    insertion.key_delay_ms,
+                );
             }
         }
 
-        if buffer.len() > MAX_TRIGGER_LENGTH {
-            buffer.remove(0);
+        thread::sleep(Duration::from_millis(insertion.post_expansion_delay_ms));
+
+        if let Err(e) = self.db.increment_usage(snippet_id) {
+            eprintln!("Failed to increment usage count: {}", e);
         }
 
-        let buffer_str = buffer.clone();
-        let last_time = *last_key_time;
-        drop(buffer);
-        drop(last_key_time);
+        // Backspace can only undo plain-text expansions, and only while the
+        // caret is still at the end of the inserted text.
+        if snippet.is_html || chars_after_cursor > 0 {
+            return None;
+        }
+        Some(LastExpansion {
+            typed: expansion.typed.clone(),
+            trailing,
+            inserted_chars: content.chars().count() + trailing.map_or(0, |_| 1),
+        })
+    }
 
+    /// Asks the user for the values of `fields` while the trigger is still in
+    /// place. Returns `None` when the form was dismissed, or when the window
+    /// the trigger was typed in does not get the focus back afterwards, so
+    /// the expansion is never typed somewhere else.
+    fn fill_in_form(&self, snippet: &Snippet, fields: Vec<FormField>) -> Option<HashMap<String, String>> {
+        let Some(app) = self.app.get() else {
+            eprintln!("Cannot open fill-in form before the app is running");
+            return None;
+        };
+        let request = FormRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            trigger: snippet.trigger.clone(),
+            description: snippet.description.clone(),
+            fields,
+        };
 
-        let handler = Arc::new(self.clone_for_timer());
-        
-        thread::spawn(move || {
-            thread::sleep(buffer_timeout);
-            
-            let current_last_time = *handler.last_key_time.lock().unwrap();
-            if current_last_time == last_time {
-                handler.check_and_replace_snippet(&buffer_str);
+        // Unknown where the focus cannot be read; the form is gone by the
+        // time `ask` returns either way.
+        let target = focused_window();
+        self.engine.lock().unwrap().suspend();
+        let values = self.forms.ask(app, request);
+        self.engine.lock().unwrap().resume();
+        let values = values?;
+
+        if let Some(target) = target {
+            if !wait_for_focus(&target, FOCUS_RETURN_TIMEOUT) {
+                eprintln!("Window {} did not get the focus back, not expanding '{}'", target, snippet.trigger);
+                return None;
             }
-        });
+        }
+        Some(values)
     }
 
-    fn clone_for_timer(&self) -> Self {
-        Self {
-            buffer: Arc::clone(&self.buffer),
-            last_key_time: Arc::clone(&self.last_key_time),
-            db: Arc::clone(&self.db),
-            clipboard: Arc::clone(&self.clipboard),
-            enigo: Arc::clone(&self.enigo),
+    /// Inserts content through the clipboard: saves what the user had copied,
+    /// lets `fill` write the new contents, pastes them and puts the saved
+    /// contents back. Returns whether the paste was sent.
+    fn paste_via_clipboard(
+        &self,
+        fill: impl FnOnce(&mut dyn ClipboardBackend) -> Result<(), Box<dyn Error + Send + Sync>>,
+        insertion: &InsertionSettings,
+    ) -> bool {
+        let snapshot = self.clipboard.lock().unwrap().snapshot();
+
+        let filled = fill(&mut **self.clipboard.lock().unwrap());
+        if let Err(e) = filled {
+            eprintln!("Failed to set clipboard: {}", e);
+            self.restore_clipboard(snapshot);
+            return false;
         }
+
+        thread::sleep(Duration::from_millis(insertion.clipboard_settle_delay_ms));
+
+        let pasted = !self.aborted() && self.send_paste_chord(&insertion.paste_chord, insertion.paste_delay_ms);
+
+        thread::sleep(Duration::from_millis(insertion.clipboard_restore_delay_ms));
+        self.restore_clipboard(snapshot);
+
+        pasted
     }
 
-    fn check_and_replace_snippet(&self, buffer: &str) {
-        if buffer.is_empty() {
-            return;
+    /// Presses `chord` (see `insertion_profile::parse_chord`), waiting
+    /// `delay_ms` between its keys. Returns whether it was delivered.
+    fn send_paste_chord(&self, chord: &str, delay_ms: u64) -> bool {
+        let (modifiers, key) = match parse_chord(chord) {
+            Ok(keys) => keys,
+            Err(e) => {
+                eprintln!("{}", e);
+                return false;
+            }
+        };
+
+        let mut injector = self.injector.lock().unwrap();
+        let mut pressed = Vec::new();
+        let mut delivered = true;
+        for modifier in modifiers {
+            if let Err(e) = self.inject_key(&mut **injector, modifier, Direction::Press) {
+                eprintln!("Failed to press {:?}: {}", modifier, e);
+                delivered = false;
+                break;
+            }
+            pressed.push(modifier);
+            thread::sleep(Duration::from_millis(delay_ms));
+        }
+
+        if delivered {
+            if let Err(e) = self.inject_key(&mut **injector, key, Direction::Click) {
+                eprintln!("Failed to press {:?}: {}", key, e);
+                delivered = false;
+            }
+            thread::sleep(Duration::from_millis(delay_ms));
+        }
+
+        for modifier in pressed.into_iter().rev() {
+            if let Err(e) = self.inject_key(&mut **injector, modifier, Direction::Release) {
+                eprintln!("Failed to release {:?}: {}", modifier, e);
+            }
         }
 
-        let snippets = match self.db.get_all_snippets() {
-            Ok(snippets) => snippets,
+        delivered
+    }
+
+    /// Looks up the profile for the focused application and lays it over the
+    /// global settings.
+    fn insertion_settings(&self) -> InsertionSettings {
+        let settings = self.engine.lock().unwrap().settings().clone();
+        let mut insertion = InsertionSettings {
+            paste_chord: DEFAULT_PASTE_CHORD.to_string(),
+            insert_method: None,
+            paste_threshold_chars: settings.paste_threshold_chars,
+            pre_expansion_delay_ms: settings.pre_expansion_delay_ms,
+            key_delay_ms: settings.key_delay_ms,
+            post_delete_delay_ms: settings.post_delete_delay_ms,
+            clipboard_settle_delay_ms: settings.clipboard_settle_delay_ms,
+            paste_delay_ms: settings.paste_delay_ms,
+            clipboard_restore_delay_ms: settings.clipboard_restore_delay_ms,
+            post_expansion_delay_ms: settings.post_expansion_delay_ms,
+        };
+
+        let profiles = match self.db.get_insertion_profiles() {
+            Ok(profiles) => profiles,
             Err(e) => {
-                eprintln!("Failed to get snippets: {}", e);
-                return;
+                eprintln!("Failed to load insertion profiles: {}", e);
+                return insertion;
             }
         };
+        // Looking up the focused window runs external programs, so skip it
+        // when there is no profile to match.
+        if profiles.is_empty() {
+            return insertion;
+        }
+        let window_classes = focused_window_classes();
+        if window_classes.is_empty() {
+            return insertion;
+        }
 
-        for snippet in snippets {
-            let trigger = &snippet.trigger;
+        if let Some(profile) = profiles.into_iter().find(|profile| profile.matches(&window_classes)) {
+            insertion.paste_chord = profile.paste_chord;
+            insertion.insert_method = profile.insert_method;
+            insertion.key_delay_ms = profile.key_delay_ms.unwrap_or(insertion.key_delay_ms);
+            insertion.paste_delay_ms = profile.paste_delay_ms.unwrap_or(insertion.paste_delay_ms);
+            insertion.clipboard_restore_delay_ms = profile
+                .clipboard_restore_delay_ms
+                .unwrap_or(insertion.clipboard_restore_delay_ms);
+        }
 
-            if buffer.ends_with(trigger) {
-                self.replace_trigger_with_content(trigger, &snippet.content, &snippet.id, snippet.is_html);
+        insertion
+    }
 
-                self.buffer.lock().unwrap().clear();
-                break;
+    /// Puts back what was on the clipboard before a pasted expansion.
+    fn restore_clipboard(&self, snapshot: ClipboardSnapshot) {
+        if let Err(e) = self.clipboard.lock().unwrap().restore(snapshot) {
+            eprintln!("Failed to restore clipboard: {}", e);
+        }
+    }
+
+    /// Parses text of the snippet triggered by `root` and inlines the
+    /// snippets it references, as they are saved now.
+    fn template_tokens(&self, root: &str, source: &str, is_html: bool) -> Result<Vec<Token>, String> {
+        let tokens = template::parse(source)?;
+        template::resolve_snippets(tokens, root, is_html, &|trigger| self.db.get_snippet_by_trigger(trigger))
+    }
+
+    /// Fields of the fill-in form for `snippet`, from its content, its plain
+    /// text alternative and the snippets they reference.
+    fn form_fields(&self, snippet: &Snippet) -> Vec<FormField> {
+        let sources = std::iter::once((&snippet.content, snippet.is_html))
+            .chain(snippet.plain_text.iter().map(|plain_text| (plain_text, false)));
+
+        let mut fields: Vec<FormField> = Vec::new();
+        for (source, is_html) in sources {
+            let Ok(tokens) = self.template_tokens(&snippet.trigger, source, is_html) else {
+                continue;
+            };
+            for field in template::form_fields(&tokens) {
+                if !fields.iter().any(|known| known.name == field.name) {
+                    fields.push(field);
+                }
+            }
+        }
+        fields
+    }
+
+    /// Evaluates the variables in text of the snippet triggered by `root`,
+    /// falling back to the raw text when it does not parse (for example
+    /// snippets saved before variables existed) or a referenced snippet is
+    /// gone. `fields` holds the values entered in the fill-in form.
+    fn render_template(&self, root: &str, source: &str, is_html: bool, fields: &HashMap<String, String>) -> String {
+        let tokens = match self.template_tokens(root, source, is_html) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                eprintln!("Failed to parse snippet content: {}", e);
+                return source.to_string();
             }
+        };
+
+        let mut context = RenderContext {
+            fields: fields.clone(),
+            ..RenderContext::default()
+        };
+        if template::uses_clipboard(&tokens) {
+            let clipboard = self.clipboard.lock().unwrap();
+            context.clipboard_text = clipboard
+                .get_text()
+                .map_err(|e| eprintln!("Failed to read clipboard text: {}", e))
+                .ok();
+            context.clipboard_html = clipboard.get_html().ok();
         }
+
+        template::render(&tokens, is_html, &context)
     }
 
-    fn replace_trigger_with_content(&self, trigger: &str, content: &str, snippet_id: &str, is_html: bool) {       
-        thread::sleep(Duration::from_millis(150));
+    /// Replaces the content of the last expansion with the trigger that was
+    /// typed. The Backspace that asked for the undo has already removed one
+    /// inserted character.
+    fn undo_expansion(&self, last_expansion: &LastExpansion) {
+        let insertion = self.insertion_settings();
+
+        thread::sleep(Duration::from_millis(insertion.pre_expansion_delay_ms));
 
         {
-            let mut enigo = self.enigo.lock().unwrap();            
-            for i in 0..trigger.len() {
-                if let Err(e) = enigo.key(Key::Backspace, enigo::Direction::Click) {
-                    eprintln!("Failed to send backspace {}: {:?}", i, e);
+            let mut injector = self.injector.lock().unwrap();
+            for i in 1..last_expansion.inserted_chars {
+                if self.aborted() {
+                    return;
+                }
+                if let Err(e) = self.inject_key(&mut **injector, Key::Backspace, Direction::Click) {
+                    eprintln!("Failed to send backspace {}: {}", i, e);
                     return;
                 }
-                thread::sleep(Duration::from_millis(10));
+                thread::sleep(Duration::from_millis(insertion.key_delay_ms));
             }
+
+            if self.aborted() {
+                return;
+            }
+            if let Err(e) = self.inject_text(&mut **injector, &last_expansion.typed) {
+                eprintln!("Failed to retype trigger: {}", e);
+                return;
+            }
+            self.type_trailing(&mut **injector, last_expansion.trailing);
         }
 
-        thread::sleep(Duration::from_millis(100));
+        thread::sleep(Duration::from_millis(insertion.post_expansion_delay_ms));
+    }
 
-        if !is_html {
-            let mut enigo = self.enigo.lock().unwrap();
-            
-            if let Err(e) = enigo.text(content) {
-                eprintln!("Failed to insert text: {:?}", e);
+    /// Presses Left `count` times to put the caret at the snippet's cursor
+    /// marker.
+    fn move_cursor_back(&self, injector: &mut dyn KeyInjector, count: usize, key_delay_ms: u64) {
+        for i in 0..count {
+            if let Err(e) = self.inject_key(injector, Key::LeftArrow, Direction::Click) {
+                eprintln!("Failed to move cursor {}: {}", i, e);
                 return;
             }
-            
-            thread::sleep(Duration::from_millis(100));
-            
-            if let Err(e) = self.db.increment_usage(snippet_id) {
-                eprintln!("Failed to increment usage count: {}", e);
+            thread::sleep(Duration::from_millis(key_delay_ms));
+        }
+    }
+
+    /// Re-types the separator that completed the trigger.
+    fn type_trailing(&self, injector: &mut dyn KeyInjector, trailing: Option<char>) {
+        let result = match trailing {
+            Some('\n') => self.inject_key(injector, Key::Return, Direction::Click),
+            Some('\t') => self.inject_key(injector, Key::Tab, Direction::Click),
+            Some(ch) => self.inject_text(injector, &ch.to_string()),
+            None => return,
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to retype separator: {}", e);
+        }
+    }
+
+    /// Sends a key through the injector after announcing it to the engine,
+    /// so its echo from the listener is not mistaken for the user's input.
+    fn inject_key(&self, injector: &mut dyn KeyInjector, key: Key, direction: Direction) -> Result<(), String> {
+        if direction != Direction::Release {
+            if let Some(expected) = expected_key(key) {
+                self.engine.lock().unwrap().expect(expected);
             }
+        }
+        injector.key(key, direction)
+    }
+
+    /// Types text through the injector after announcing it to the engine.
+    fn inject_text(&self, injector: &mut dyn KeyInjector, text: &str) -> Result<(), String> {
+        self.engine.lock().unwrap().expect_text(text);
+        injector.text(text)
+    }
+
+    /// Whether the user typed or clicked since the current action started.
+    fn aborted(&self) -> bool {
+        self.engine.lock().unwrap().aborted()
+    }
+
+    /// Tells the frontend that an expansion was stopped because the user
+    /// typed or clicked while it was being injected.
+    fn report_aborted(&self, trigger: &str, undo: bool) {
+        eprintln!("Expansion of '{}' aborted by user input", trigger);
+        let Some(app) = self.app.get() else {
             return;
+        };
+        let payload = ExpansionAborted {
+            trigger: trigger.to_string(),
+            undo,
+        };
+        if let Err(e) = app.emit(EXPANSION_ABORTED_EVENT, payload) {
+            eprintln!("Failed to report aborted expansion: {}", e);
         }
+    }
 
-        {
-            let mut clipboard = self.clipboard.lock().unwrap();
-            if let Err(e) = clipboard.set_html(content) {
-                eprintln!("Failed to set clipboard HTML: {}", e);
-                return;
+    #[allow(dead_code)]
+    pub fn get_buffer(&self) -> String {
+        self.engine.lock().unwrap().buffer().to_string()
+    }
+
+    #[allow(dead_code)]
+    pub fn clear_buffer(&self) {
+        self.engine.lock().unwrap().clear_buffer();
+    }
+}
+
+/// The key event the listener will see for a synthesized key. Modifiers need
+/// no announcement since modifier presses never count as typing.
+fn expected_key(key: Key) -> Option<Expected> {
+    match key {
+        Key::Backspace => Some(Expected::Key(RdevKey::Backspace)),
+        Key::LeftArrow => Some(Expected::Key(RdevKey::LeftArrow)),
+        Key::Return => Some(Expected::Key(RdevKey::Return)),
+        Key::Tab => Some(Expected::Key(RdevKey::Tab)),
+        #[cfg(not(target_os = "macos"))]
+        Key::Insert => Some(Expected::Key(RdevKey::Insert)),
+        Key::Unicode(ch) => Some(Expected::Char(ch)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard_handler::MockClipboard;
+    use crate::clipboard_handler::MockClipboardContents;
+    use crate::input_backend::{Injected, MockDocument, MockInjector, MockSource};
+
+    /// A handler wired to mock backends, listening on its own threads like
+    /// the real one. Keys typed by the "user" go both into the document and
+    /// through the event source.
+    struct Harness {
+        handler: Arc<KeyboardHandler>,
+        events: Sender<Event>,
+        document: Arc<Mutex<MockDocument>>,
+        clipboard: Arc<Mutex<MockClipboardContents>>,
+    }
+
+    impl Harness {
+        fn start(snippets: Vec<Snippet>, configure: impl FnOnce(&mut SnippetSettings)) -> Self {
+            let db = Arc::new(Database::in_memory().unwrap());
+            for snippet in &snippets {
+                db.create_snippet(snippet).unwrap();
             }
-        }
 
-        thread::sleep(Duration::from_millis(100));
+            let (source, events) = MockSource::new();
+            let clipboard = MockClipboard::new();
+            let injector = MockInjector::with_clipboard(clipboard.clone());
+            let document = injector.document();
+            let contents = clipboard.contents();
+            let handler = Arc::new(KeyboardHandler::with_backends(
+                db,
+                Arc::new(source),
+                Box::new(injector),
+                Box::new(clipboard),
+            ));
+
+            let mut settings = SnippetSettings::new("1".to_string(), 30);
+            settings.clipboard_restore_delay_ms = 0;
+            settings.pre_expansion_delay_ms = 0;
+            settings.key_delay_ms = 0;
+            settings.post_delete_delay_ms = 0;
+            settings.clipboard_settle_delay_ms = 0;
+            settings.paste_delay_ms = 0;
+            settings.post_expansion_delay_ms = 0;
+            configure(&mut settings);
+            handler.update_settings(settings);
+            Arc::clone(&handler).start_listening();
+
+            Self {
+                handler,
+                events,
+                document,
+                clipboard: contents,
+            }
+        }
 
-        {
-            let mut enigo = self.enigo.lock().unwrap();
-            #[cfg(target_os = "macos")]
-            {
-                if let Err(e) = enigo.key(Key::Meta, enigo::Direction::Press) {
-                    eprintln!("Failed to press Meta: {:?}", e);
-                    return;
-                }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::V, enigo::Direction::Click) {
-                    eprintln!("Failed to press V: {:?}", e);
-                    let _ = enigo.key(Key::Meta, enigo::Direction::Release);
-                    return;
-                }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::Meta, enigo::Direction::Release) {
-                    eprintln!("Failed to release Meta: {:?}", e);
-                }
+        fn type_text(&self, text: &str) {
+            for ch in text.chars() {
+                self.document.lock().unwrap().insert(&ch.to_string());
+                let key = if ch == ' ' { RdevKey::Space } else { RdevKey::Unknown(ch as u32) };
+                self.events.send(MockSource::key_press(key, Some(&ch.to_string()))).unwrap();
             }
+        }
 
-            #[cfg(target_os = "windows")]
+        fn press_backspace(&self) {
             {
-                if let Err(e) = enigo.key(Key::Control, enigo::Direction::Press) {
-                    eprintln!("Failed to press Control: {:?}", e);
-                    return;
-                }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::V, enigo::Direction::Click) {
-                    eprintln!("Failed to press V: {:?}", e);
-                    let _ = enigo.key(Key::Control, enigo::Direction::Release);
-                    return;
-                }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::Control, enigo::Direction::Release) {
-                    eprintln!("Failed to release Control: {:?}", e);
+                let mut document = self.document.lock().unwrap();
+                if document.caret > 0 {
+                    document.caret -= 1;
+                    let caret = document.caret;
+                    document.text.remove(caret);
                 }
             }
+            self.events.send(MockSource::key_press(RdevKey::Backspace, None)).unwrap();
+        }
 
-            #[cfg(target_os = "linux")]
-            {
-                if let Err(e) = enigo.key(Key::Control, enigo::Direction::Press) {
-                    eprintln!("Failed to press Control: {:?}", e);
-                    return;
-                }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::V, enigo::Direction::Click) {
-                    eprintln!("Failed to press V: {:?}", e);
-                    let _ = enigo.key(Key::Control, enigo::Direction::Release);
+        /// Waits for the document to read `expected` and for the handler to
+        /// be done with the action that produced it.
+        fn wait_for(&self, expected: &str) {
+            let deadline = Instant::now() + Duration::from_secs(2);
+            loop {
+                let contents = self.document.lock().unwrap().contents();
+                let idle = !self.handler.engine.lock().unwrap().injecting();
+                if contents == expected && idle {
                     return;
                 }
-                thread::sleep(Duration::from_millis(50));
-                
-                if let Err(e) = enigo.key(Key::Control, enigo::Direction::Release) {
-                    eprintln!("Failed to release Control: {:?}", e);
+                if Instant::now() > deadline {
+                    panic!("document is {:?} (idle: {}), expected {:?}", contents, idle, expected);
                 }
+                thread::sleep(Duration::from_millis(5));
             }
-            
         }
 
-        thread::sleep(Duration::from_millis(100));
+        fn caret(&self) -> usize {
+            self.document.lock().unwrap().caret
+        }
 
-        {
+        fn copy(&self, text: &str, html: Option<&str>) {
             let mut clipboard = self.clipboard.lock().unwrap();
-            if let Err(e) = clipboard.clear() {
-                eprintln!("Failed to clear clipboard: {}", e);
-            }
+            clipboard.text = Some(text.to_string());
+            clipboard.html = html.map(str::to_string);
         }
 
-        thread::sleep(Duration::from_millis(50));
+        fn clipboard(&self) -> (Option<String>, Option<String>) {
+            let clipboard = self.clipboard.lock().unwrap();
+            (clipboard.text.clone(), clipboard.html.clone())
+        }
 
-        if let Err(e) = self.db.increment_usage(snippet_id) {
-            eprintln!("Failed to increment usage count: {}", e);
+        fn typed_text(&self) -> Vec<String> {
+            self.document
+                .lock()
+                .unwrap()
+                .injected
+                .iter()
+                .filter_map(|injected| match injected {
+                    Injected::Text(text) => Some(text.clone()),
+                    Injected::Key(..) => None,
+                })
+                .collect()
         }
-        
     }
 
-    #[allow(dead_code)]
-    pub fn get_buffer(&self) -> String {
-        self.buffer.lock().unwrap().clone()
+    fn snippet(trigger: &str, content: &str) -> Snippet {
+        Snippet::new(trigger.to_string(), content.to_string(), None, Vec::new(), false)
     }
 
-    #[allow(dead_code)]
-    pub fn clear_buffer(&self) {
-        self.buffer.lock().unwrap().clear();
+    #[test]
+    fn expands_a_plain_trigger_after_the_pause() {
+        let harness = Harness::start(vec![snippet("sig", "Best regards")], |_| {});
+
+        harness.type_text("hi sig");
+        harness.wait_for("hi Best regards");
+        assert_eq!(harness.caret(), "hi Best regards".len());
+    }
+
+    #[test]
+    fn places_the_caret_at_the_cursor_marker() {
+        let harness = Harness::start(vec![snippet("fn", "foo($|$);")], |_| {});
+
+        harness.type_text("fn");
+        harness.wait_for("foo();");
+        assert_eq!(harness.caret(), 4);
+    }
+
+    #[test]
+    fn backspace_right_after_an_expansion_restores_the_trigger() {
+        let harness = Harness::start(vec![snippet("sig", "Best regards")], |_| {});
+
+        harness.type_text("hi sig");
+        harness.wait_for("hi Best regards");
+
+        harness.press_backspace();
+        harness.wait_for("hi sig");
+        assert_eq!(harness.caret(), 6);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pastes_through_the_clipboard_and_restores_it() {
+        let pasted = Snippet {
+            insert_method: InsertMethod::Paste,
+            ..snippet("sig", "Best regards")
+        };
+        let harness = Harness::start(vec![pasted], |_| {});
+        harness.copy("copied", None);
+
+        harness.type_text("hi sig");
+        harness.wait_for("hi Best regards");
+        assert!(harness.typed_text().is_empty());
+        assert_eq!(harness.clipboard(), (Some("copied".to_string()), None));
+    }
+
+    #[test]
+    fn pastes_html_snippets_and_restores_both_flavours() {
+        let html = Snippet::new(
+            "sig".to_string(),
+            "<b>Best</b> regards".to_string(),
+            None,
+            Vec::new(),
+            true,
+        );
+        let harness = Harness::start(vec![html], |_| {});
+        harness.copy("copied", Some("<i>copied</i>"));
+
+        harness.type_text("hi sig");
+        harness.wait_for("hi Best regards");
+        assert!(harness.typed_text().is_empty());
+        assert_eq!(
+            harness.clipboard(),
+            (Some("copied".to_string()), Some("<i>copied</i>".to_string()))
+        );
+    }
+
+    #[test]
+    fn retypes_the_delimiter_after_the_content() {
+        let harness = Harness::start(vec![snippet("brb", "be right back")], |settings| {
+            settings.expand_on_delimiter = true;
+            settings.time_delay_ms = 10_000;
+        });
+
+        harness.type_text("ok brb ");
+        harness.wait_for("ok be right back ");
+        assert_eq!(harness.caret(), "ok be right back ".len());
+    }
+}
@@ -1,12 +1,20 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod case_style;
 mod command;
 mod config;
+mod expansion_engine;
+mod fill_in_form;
 mod snippet;
 mod snippet_settings;
 mod keyboard_handler;
 mod clipboard_handler;
+mod html_text;
+mod input_backend;
+mod insertion_profile;
+mod template;
+mod trigger_index;
 
 use command::*;
 use config::Database;
@@ -15,6 +23,7 @@ use std::sync::Arc;
 
 fn main() {
     let db = Arc::new(Database::new().expect("Failed to initialize database"));
+    let keyboard_handler = Arc::new(KeyboardHandler::new(db.clone()));
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_autostart::init(
@@ -22,15 +31,10 @@ fn main() {
             Some(vec![]),
         ))
         .manage(db.clone())
-        .setup(move |_app| {
-            let db_for_keyboard = db.clone();
-            std::thread::spawn(move || {
-                let keyboard_handler = Arc::new(KeyboardHandler::new(db_for_keyboard));
-                keyboard_handler.start_listening();
-                loop {
-                    std::thread::sleep(std::time::Duration::from_secs(60));
-                }
-            });
+        .manage(keyboard_handler.clone())
+        .setup(move |app| {
+            keyboard_handler.set_app_handle(app.handle().clone());
+            keyboard_handler.start_listening();
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -44,7 +48,14 @@ fn main() {
             import_snippets,
             increment_usage,
             update_snippet_settings,
-            get_snippets_settings
+            get_snippets_settings,
+            get_insertion_profiles,
+            create_insertion_profile,
+            update_insertion_profile,
+            delete_insertion_profile,
+            get_form_request,
+            submit_form,
+            cancel_form
         ])
         .build(tauri::generate_context!())
         .expect("error while running tauri application")
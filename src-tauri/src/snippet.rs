@@ -1,6 +1,23 @@
+use crate::template;
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Marks where the caret should end up once the content has been inserted.
+pub const CURSOR_MARKER: &str = "$|$";
+
+/// Strips cursor markers from `content`, returning the remaining text and the
+/// number of characters that follow the first marker.
+pub fn split_cursor_marker(content: &str) -> (String, usize) {
+    match content.split_once(CURSOR_MARKER) {
+        Some((before, after)) => {
+            let after = after.replace(CURSOR_MARKER, "");
+            let chars_after = after.chars().count();
+            (format!("{}{}", before, after), chars_after)
+        }
+        None => (content.to_string(), 0),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Snippet {
     pub id: String,
@@ -12,6 +29,128 @@ pub struct Snippet {
     pub updated_at: u64,
     pub usage_count: u32,
     pub is_html: bool,  // Flag to indicate if content is HTML
+    #[serde(default)]
+    pub priority: i32,  // Breaks ties between triggers matching the same length
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    #[serde(default)]
+    pub expand_on_delimiter: Option<bool>,  // Overrides SnippetSettings::expand_on_delimiter when set
+    #[serde(default)]
+    pub propagate_case: bool,  // Match the trigger in any case and carry that case into the content
+    #[serde(default)]
+    pub plain_text: Option<String>,  // Hand-written fallback for HTML content; generated when missing
+    #[serde(default)]
+    pub insert_method: InsertMethod,  // How plain-text content reaches the application; HTML is always pasted
+}
+
+/// Where in the surrounding text a trigger is allowed to fire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// Fires wherever the trigger is typed, even inside another word.
+    #[default]
+    Anywhere,
+    /// The trigger has to start a word.
+    WordStart,
+    /// The trigger has to start a word and be followed by a separator.
+    WholeWord,
+}
+
+impl MatchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchMode::Anywhere => "anywhere",
+            MatchMode::WordStart => "word_start",
+            MatchMode::WholeWord => "whole_word",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "anywhere" => Some(MatchMode::Anywhere),
+            "word_start" => Some(MatchMode::WordStart),
+            "whole_word" => Some(MatchMode::WholeWord),
+            _ => None,
+        }
+    }
+}
+
+/// How the expansion of a plain-text snippet is inserted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InsertMethod {
+    /// Type the content key by key.
+    Type,
+    /// Put the content on the clipboard and send the paste shortcut.
+    Paste,
+    /// Paste content longer than `SnippetSettings::paste_threshold_chars`,
+    /// type anything shorter.
+    #[default]
+    Auto,
+}
+
+impl InsertMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InsertMethod::Type => "type",
+            InsertMethod::Paste => "paste",
+            InsertMethod::Auto => "auto",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "type" => Some(InsertMethod::Type),
+            "paste" => Some(InsertMethod::Paste),
+            "auto" => Some(InsertMethod::Auto),
+            _ => None,
+        }
+    }
+
+    /// Whether `content` should go through the clipboard.
+    pub fn pastes(self, content: &str, threshold_chars: u64) -> bool {
+        match self {
+            InsertMethod::Type => false,
+            InsertMethod::Paste => true,
+            InsertMethod::Auto => content.chars().count() as u64 > threshold_chars,
+        }
+    }
+}
+
+/// Optional per-snippet settings accepted by the create and update commands.
+/// Fields left as `None` keep the snippet's current value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SnippetOptions {
+    pub priority: Option<i32>,
+    pub match_mode: Option<MatchMode>,
+    pub expand_on_delimiter: Option<bool>,
+    pub propagate_case: Option<bool>,
+    /// An empty string removes the hand-written plain text.
+    pub plain_text: Option<String>,
+    pub insert_method: Option<InsertMethod>,
+}
+
+impl SnippetOptions {
+    pub fn apply_to(&self, snippet: &mut Snippet) {
+        if let Some(priority) = self.priority {
+            snippet.priority = priority;
+        }
+        if let Some(match_mode) = self.match_mode {
+            snippet.match_mode = match_mode;
+        }
+        if let Some(expand_on_delimiter) = self.expand_on_delimiter {
+            snippet.expand_on_delimiter = Some(expand_on_delimiter);
+        }
+        if let Some(propagate_case) = self.propagate_case {
+            snippet.propagate_case = propagate_case;
+        }
+        if let Some(plain_text) = &self.plain_text {
+            snippet.plain_text = Some(plain_text.clone()).filter(|text| !text.is_empty());
+        }
+        if let Some(insert_method) = self.insert_method {
+            snippet.insert_method = insert_method;
+        }
+    }
 }
 
 impl Snippet {
@@ -31,10 +170,16 @@ impl Snippet {
             updated_at: timestamp,
             usage_count: 0,
             is_html,
+            priority: 0,
+            match_mode: MatchMode::default(),
+            expand_on_delimiter: None,
+            propagate_case: false,
+            plain_text: None,
+            insert_method: InsertMethod::default(),
         }
     }
 
-    pub fn _update(&mut self, trigger: String, content: String, description: Option<String>, tags: Vec<String>, is_html: bool) {
+    pub fn update(&mut self, trigger: String, content: String, description: Option<String>, tags: Vec<String>, is_html: bool) {
         self.trigger = trigger;
         self.content = content;
         self.description = description;
@@ -46,6 +191,52 @@ impl Snippet {
             .as_secs();
     }
 
+    /// Reports template errors when the snippet is saved rather than when it
+    /// expands.
+    pub fn validate(&self) -> Result<(), String> {
+        template::parse(&self.content).map_err(|e| format!("Invalid snippet content: {}", e))?;
+        if let Some(plain_text) = &self.plain_text {
+            template::parse(plain_text).map_err(|e| format!("Invalid plain text: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the `{{snippet:...}}` references in this version of the
+    /// snippet resolve, looking other snippets up with `lookup`.
+    pub fn validate_references(
+        &self,
+        lookup: &dyn Fn(&str) -> Result<Option<Snippet>, String>,
+    ) -> Result<(), String> {
+        // The saved copy may be stale or missing; cycles back to this
+        // snippet have to be found through its new content.
+        let lookup = |trigger: &str| {
+            if trigger == self.trigger {
+                Ok(Some(self.clone()))
+            } else {
+                // Under its old trigger the snippet will be gone once saved.
+                lookup(trigger).map(|found| found.filter(|snippet| snippet.id != self.id))
+            }
+        };
+
+        let tokens = template::parse(&self.content)?;
+        template::resolve_snippets(tokens, &self.trigger, self.is_html, &lookup)?;
+        if let Some(plain_text) = &self.plain_text {
+            let tokens = template::parse(plain_text)?;
+            template::resolve_snippets(tokens, &self.trigger, false, &lookup)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the content or plain text alternative includes the snippet
+    /// with `trigger` through `{{snippet:...}}`.
+    pub fn references(&self, trigger: &str) -> bool {
+        [Some(&self.content), self.plain_text.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|text| template::parse(text).ok())
+            .any(|tokens| template::snippet_references(&tokens).contains(&trigger))
+    }
+
     pub fn _increment_usage(&mut self) {
         self.usage_count += 1;
         self.updated_at = SystemTime::now()
@@ -1,16 +1,78 @@
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Characters that complete a trigger when expanding on delimiters: space,
+/// Tab, Enter and common punctuation.
+pub const DEFAULT_DELIMITERS: &str = " \t\n.,;:!?";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnippetSettings {
     pub id: String,
     pub time_delay_ms: u64,
     pub created_at: u64,
     pub updated_at: u64,
+    pub expand_on_delimiter: bool,  // Expand as soon as a delimiter follows the trigger
+    pub delimiters: String,         // Each character is a delimiter; Tab is '\t', Enter is '\n'
+    pub suppress_after_undo: bool,  // Keep a trigger restored by Backspace from expanding again
+    pub clipboard_restore_delay_ms: u64,  // Wait after pasting before the old clipboard is put back
+    pub paste_threshold_chars: u64,  // `InsertMethod::Auto` pastes plain text longer than this
+    pub pre_expansion_delay_ms: u64,  // Wait before the trigger, or an expansion being undone, is deleted
+    pub key_delay_ms: u64,  // Wait after each synthesized Backspace or arrow key
+    pub post_delete_delay_ms: u64,  // Wait between deleting the trigger and inserting the content
+    pub clipboard_settle_delay_ms: u64,  // Wait between filling the clipboard and pasting
+    pub paste_delay_ms: u64,  // Wait between the keys of the paste chord
+    pub post_expansion_delay_ms: u64,  // Wait after the content is in before the expansion counts as done
+}
+
+/// Optional settings accepted by `update_snippet_settings`. Fields left as
+/// `None` keep their current value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SettingsOptions {
+    pub expand_on_delimiter: Option<bool>,
+    pub delimiters: Option<String>,
+    pub suppress_after_undo: Option<bool>,
+    pub clipboard_restore_delay_ms: Option<u64>,
+    pub paste_threshold_chars: Option<u64>,
+    pub pre_expansion_delay_ms: Option<u64>,
+    pub key_delay_ms: Option<u64>,
+    pub post_delete_delay_ms: Option<u64>,
+    pub clipboard_settle_delay_ms: Option<u64>,
+    pub paste_delay_ms: Option<u64>,
+    pub post_expansion_delay_ms: Option<u64>,
+}
+
+impl SettingsOptions {
+    pub fn apply_to(&self, settings: &mut SnippetSettings) {
+        if let Some(expand_on_delimiter) = self.expand_on_delimiter {
+            settings.expand_on_delimiter = expand_on_delimiter;
+        }
+        if let Some(delimiters) = &self.delimiters {
+            settings.delimiters = delimiters.clone();
+        }
+        if let Some(suppress_after_undo) = self.suppress_after_undo {
+            settings.suppress_after_undo = suppress_after_undo;
+        }
+
+        let timings = [
+            (self.clipboard_restore_delay_ms, &mut settings.clipboard_restore_delay_ms),
+            (self.paste_threshold_chars, &mut settings.paste_threshold_chars),
+            (self.pre_expansion_delay_ms, &mut settings.pre_expansion_delay_ms),
+            (self.key_delay_ms, &mut settings.key_delay_ms),
+            (self.post_delete_delay_ms, &mut settings.post_delete_delay_ms),
+            (self.clipboard_settle_delay_ms, &mut settings.clipboard_settle_delay_ms),
+            (self.paste_delay_ms, &mut settings.paste_delay_ms),
+            (self.post_expansion_delay_ms, &mut settings.post_expansion_delay_ms),
+        ];
+        for (value, field) in timings {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+    }
 }
 
 impl SnippetSettings {
-    pub fn _new(id: String, time_delay_ms: u64) -> Self {
+    pub fn new(id: String, time_delay_ms: u64) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -20,15 +82,30 @@ impl SnippetSettings {
             id,
             time_delay_ms,
             created_at: timestamp,
-            updated_at: timestamp
+            updated_at: timestamp,
+            expand_on_delimiter: false,
+            delimiters: DEFAULT_DELIMITERS.to_string(),
+            suppress_after_undo: true,
+            clipboard_restore_delay_ms: 100,
+            paste_threshold_chars: 200,
+            pre_expansion_delay_ms: 150,
+            key_delay_ms: 10,
+            post_delete_delay_ms: 100,
+            clipboard_settle_delay_ms: 100,
+            paste_delay_ms: 50,
+            post_expansion_delay_ms: 100,
         }
     }
 
-    pub fn _update(&mut self, time_delay_ms: u64) {
+    pub fn update(&mut self, time_delay_ms: u64) {
         self.time_delay_ms = time_delay_ms;
         self.updated_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
     }
-}
\ No newline at end of file
+
+    pub fn is_delimiter(&self, ch: char) -> bool {
+        self.delimiters.contains(ch)
+    }
+}
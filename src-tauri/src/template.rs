@@ -0,0 +1,464 @@
+use chrono::format::{Item, StrftimeItems};
+use crate::html_text::html_to_plain_text;
+use crate::snippet::Snippet;
+use chrono::{Duration, Local};
+use serde::Serialize;
+use std::collections::HashMap;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+/// Longest chain of `{{snippet:...}}` references followed before giving up.
+pub const MAX_SNIPPET_DEPTH: usize = 8;
+
+/// A piece of snippet content: literal text or a `{{...}}` variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Text(String),
+    Variable(Variable),
+}
+
+/// Variables evaluated when a snippet is expanded.
+///
+/// * `{{date}}`, `{{date:%d/%m/%Y}}`, `{{date+3d}}` - current date, optionally
+///   shifted by `s`, `m`, `h`, `d` or `w` and formatted with strftime syntax
+/// * `{{time}}`, `{{time-30m:%H:%M:%S}}` - same for the time of day
+/// * `{{uuid}}` - a random v4 UUID
+/// * `{{random:a|b|c}}` - one of the listed choices
+/// * `{{clipboard}}`, `{{clipboard_html}}` - the clipboard's text or HTML
+/// * `{{field:name}}`, `{{multiline:notes}}`, `{{choice:priority|low|high}}` -
+///   values the user fills in before the snippet is inserted
+/// * `{{snippet:sig}}` - the content of the snippet with that trigger, see
+///   `resolve_snippets`
+///
+/// A backslash makes `\{{` a literal `{{`, for content that is itself a
+/// template, e.g. `Hello \{{name}}` expands to `Hello {{name}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variable {
+    Date { format: String, offset: Duration },
+    Time { format: String, offset: Duration },
+    Uuid,
+    Random(Vec<String>),
+    Clipboard,
+    ClipboardHtml,
+    Field(FormField),
+    Snippet(String),
+}
+
+/// A value asked for in the fill-in form. A name used by several variables is
+/// asked for once and inserted everywhere.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FormField {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: FieldKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FieldKind {
+    Text,
+    MultiLine,
+    Choice { options: Vec<String> },
+}
+
+impl Variable {
+    fn uses_clipboard(&self) -> bool {
+        matches!(self, Variable::Clipboard | Variable::ClipboardHtml)
+    }
+}
+
+/// Values read at expansion time for variables that depend on system state.
+#[derive(Debug, Default)]
+pub struct RenderContext {
+    pub clipboard_text: Option<String>,
+    pub clipboard_html: Option<String>,
+    pub fields: HashMap<String, String>,  // Fill-in form values by field name
+}
+
+/// Splits snippet content into text and variables, reporting the first
+/// malformed or unknown variable. `\{{` is kept as a literal `{{`.
+pub fn parse(content: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("{{") {
+        if let Some(before) = rest[..start].strip_suffix('\\') {
+            text.push_str(before);
+            text.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        text.push_str(&rest[..start]);
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+
+        let after_open = &rest[start + 2..];
+        let end = after_open
+            .find("}}")
+            .ok_or_else(|| format!("Unclosed '{{{{' in '{}'", truncate(&rest[start..])))?;
+
+        tokens.push(Token::Variable(parse_variable(&after_open[..end])?));
+        rest = &after_open[end + 2..];
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_variable(source: &str) -> Result<Variable, String> {
+    let source = source.trim();
+    let (head, argument) = match source.split_once(':') {
+        Some((head, argument)) => (head.trim(), Some(argument)),
+        None => (source, None),
+    };
+
+    let name_end = head
+        .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+        .unwrap_or(head.len());
+    let (name, offset) = head.split_at(name_end);
+
+    match name {
+        "date" | "time" => {
+            let offset = parse_offset(offset.trim())?;
+            let default_format = if name == "date" { DEFAULT_DATE_FORMAT } else { DEFAULT_TIME_FORMAT };
+            let format = argument.unwrap_or(default_format).to_string();
+            if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("Invalid {} format '{}'", name, format));
+            }
+
+            if name == "date" {
+                Ok(Variable::Date { format, offset })
+            } else {
+                Ok(Variable::Time { format, offset })
+            }
+        }
+        _ if !offset.trim().is_empty() => Err(format!("'{}' does not take an offset", name)),
+        "uuid" | "clipboard" | "clipboard_html" if argument.is_some() => {
+            Err(format!("'{}' does not take an argument", name))
+        }
+        "uuid" => Ok(Variable::Uuid),
+        "clipboard" => Ok(Variable::Clipboard),
+        "clipboard_html" => Ok(Variable::ClipboardHtml),
+        "random" => {
+            let choices: Vec<String> = argument
+                .unwrap_or_default()
+                .split('|')
+                .map(|choice| choice.to_string())
+                .filter(|choice| !choice.is_empty())
+                .collect();
+            if choices.is_empty() {
+                return Err("'random' needs choices, e.g. {{random:a|b|c}}".to_string());
+            }
+            Ok(Variable::Random(choices))
+        }
+        "field" | "multiline" => {
+            let field_name = argument.map(str::trim).unwrap_or_default();
+            if field_name.is_empty() {
+                return Err(format!("'{}' needs a name, e.g. {{{{{}:name}}}}", name, name));
+            }
+            let kind = if name == "field" { FieldKind::Text } else { FieldKind::MultiLine };
+            Ok(Variable::Field(FormField { name: field_name.to_string(), kind }))
+        }
+        "choice" => {
+            let mut parts = argument.unwrap_or_default().split('|').map(str::trim);
+            let field_name = parts.next().unwrap_or_default();
+            let options: Vec<String> = parts
+                .filter(|option| !option.is_empty())
+                .map(|option| option.to_string())
+                .collect();
+            if field_name.is_empty() || options.is_empty() {
+                return Err("'choice' needs a name and options, e.g. {{choice:priority|low|high}}".to_string());
+            }
+            Ok(Variable::Field(FormField {
+                name: field_name.to_string(),
+                kind: FieldKind::Choice { options },
+            }))
+        }
+        "snippet" => {
+            let trigger = argument.unwrap_or_default().trim();
+            if trigger.is_empty() {
+                return Err("'snippet' needs a trigger, e.g. {{snippet:sig}}".to_string());
+            }
+            Ok(Variable::Snippet(trigger.to_string()))
+        }
+        "" => Err(format!("Empty variable '{{{{{}}}}}'", source)),
+        _ => Err(format!("Unknown variable '{}'", name)),
+    }
+}
+
+/// Parses offsets such as `+3d` or `-30m`. An empty string means no offset.
+fn parse_offset(offset: &str) -> Result<Duration, String> {
+    if offset.is_empty() {
+        return Ok(Duration::zero());
+    }
+
+    let invalid = || format!("Invalid offset '{}', expected e.g. +3d or -2h", offset);
+    let (sign, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = offset.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+    let unit_start = rest.char_indices().last().map_or(0, |(index, _)| index);
+    let (amount, unit) = rest.split_at(unit_start);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    let duration = amount
+        .checked_mul(seconds * sign)
+        .and_then(Duration::try_seconds)
+        .ok_or_else(invalid)?;
+
+    // Dates only go a few hundred thousand years either way.
+    if Local::now().checked_add_signed(duration).is_none() {
+        return Err(format!("Offset '{}' is out of range", offset));
+    }
+    Ok(duration)
+}
+
+/// Replaces `{{snippet:...}}` references in the tokens of the snippet
+/// triggered by `root` with the tokens of the referenced snippets, found with
+/// `lookup`, recursively. `as_html` says whether the result is HTML: plain
+/// snippets are escaped into it and HTML snippets contribute their plain text
+/// alternative otherwise. Fails on missing triggers, cycles and chains longer
+/// than `MAX_SNIPPET_DEPTH`.
+pub fn resolve_snippets(
+    tokens: Vec<Token>,
+    root: &str,
+    as_html: bool,
+    lookup: &dyn Fn(&str) -> Result<Option<Snippet>, String>,
+) -> Result<Vec<Token>, String> {
+    resolve_nested(tokens, as_html, lookup, &mut vec![root.to_string()])
+}
+
+fn resolve_nested(
+    tokens: Vec<Token>,
+    as_html: bool,
+    lookup: &dyn Fn(&str) -> Result<Option<Snippet>, String>,
+    chain: &mut Vec<String>,
+) -> Result<Vec<Token>, String> {
+    let mut resolved = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        let trigger = match token {
+            Token::Variable(Variable::Snippet(trigger)) => trigger,
+            token => {
+                resolved.push(token);
+                continue;
+            }
+        };
+
+        if chain.contains(&trigger) {
+            chain.push(trigger);
+            return Err(format!("Snippet references form a cycle: {}", chain.join(" -> ")));
+        }
+        if chain.len() > MAX_SNIPPET_DEPTH {
+            return Err(format!(
+                "Snippet references are nested more than {} levels deep: {}",
+                MAX_SNIPPET_DEPTH,
+                chain.join(" -> ")
+            ));
+        }
+        let snippet = lookup(&trigger)?
+            .ok_or_else(|| format!("Referenced snippet '{}' does not exist", trigger))?;
+
+        let source = match (&snippet.plain_text, snippet.is_html && !as_html) {
+            (Some(plain_text), true) => plain_text.clone(),
+            (None, true) => html_to_plain_text(&snippet.content),
+            (_, false) => snippet.content.clone(),
+        };
+        let nested_as_html = as_html && snippet.is_html;
+        let nested = parse(&source).map_err(|e| format!("Invalid snippet '{}': {}", trigger, e))?;
+
+        chain.push(trigger);
+        let nested = resolve_nested(nested, nested_as_html, lookup, chain)?;
+        chain.pop();
+
+        for token in nested {
+            match token {
+                // Plain text inside an HTML snippet.
+                Token::Text(text) if as_html && !nested_as_html => {
+                    resolved.push(Token::Text(html_escape(&text).replace('\n', "<br>")))
+                }
+                token => resolved.push(token),
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Whether rendering `tokens` needs the clipboard contents in the context.
+pub fn uses_clipboard(tokens: &[Token]) -> bool {
+    tokens
+        .iter()
+        .any(|token| matches!(token, Token::Variable(variable) if variable.uses_clipboard()))
+}
+
+/// Triggers of the snippets `tokens` include through `{{snippet:...}}`.
+pub fn snippet_references(tokens: &[Token]) -> Vec<&str> {
+    tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Variable(Variable::Snippet(trigger)) => Some(trigger.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fields the user has to fill in before `tokens` can be rendered, in order of
+/// first use.
+pub fn form_fields(tokens: &[Token]) -> Vec<FormField> {
+    let mut fields: Vec<FormField> = Vec::new();
+    for token in tokens {
+        if let Token::Variable(Variable::Field(field)) = token {
+            if !fields.iter().any(|known| known.name == field.name) {
+                fields.push(field.clone());
+            }
+        }
+    }
+    fields
+}
+
+/// Evaluates the variables in `tokens`. Values are HTML-escaped when they end
+/// up in an HTML snippet, except `{{clipboard_html}}` which is inserted as
+/// markup; line breaks in multi-line fields become `<br>`.
+pub fn render(tokens: &[Token], escape_html: bool, context: &RenderContext) -> String {
+    let mut output = String::new();
+
+    for token in tokens {
+        match token {
+            Token::Text(text) => output.push_str(text),
+            Token::Variable(Variable::ClipboardHtml) if escape_html => match &context.clipboard_html {
+                Some(html) => output.push_str(html),
+                None => output.push_str(&html_escape(context.clipboard_text.as_deref().unwrap_or_default())),
+            },
+            Token::Variable(variable @ Variable::Field(FormField { kind: FieldKind::MultiLine, .. }))
+                if escape_html =>
+            {
+                let value = evaluate(variable, context);
+                output.push_str(&html_escape(&value).replace('\n', "<br>"));
+            }
+            Token::Variable(variable) => {
+                let value = evaluate(variable, context);
+                if escape_html {
+                    output.push_str(&html_escape(&value));
+                } else {
+                    output.push_str(&value);
+                }
+            }
+        }
+    }
+
+    output
+}
+
+fn evaluate(variable: &Variable, context: &RenderContext) -> String {
+    match variable {
+        Variable::Date { format, offset } | Variable::Time { format, offset } => {
+            // Offsets are checked when parsed, but the clock moves on.
+            let now = Local::now();
+            let shifted = now.checked_add_signed(*offset).unwrap_or_else(|| {
+                eprintln!("Date offset {} is out of range, using the current time", offset);
+                now
+            });
+            shifted.format(format).to_string()
+        }
+        Variable::Uuid => uuid::Uuid::new_v4().to_string(),
+        Variable::Random(choices) => {
+            let index = (uuid::Uuid::new_v4().as_u128() % choices.len() as u128) as usize;
+            choices[index].clone()
+        }
+        Variable::Clipboard => context.clipboard_text.clone().unwrap_or_default(),
+        Variable::ClipboardHtml => context
+            .clipboard_html
+            .clone()
+            .or_else(|| context.clipboard_text.clone())
+            .unwrap_or_default(),
+        Variable::Field(field) => context.fields.get(&field.name).cloned().unwrap_or_default(),
+        // Replaced by `resolve_snippets` before rendering.
+        Variable::Snippet(_) => String::new(),
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(20).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_outside_the_calendar_are_rejected() {
+        assert_eq!(parse_offset("+3d"), Ok(Duration::days(3)));
+        assert_eq!(parse_offset("-30m"), Ok(Duration::minutes(-30)));
+        assert!(parse_offset("+99999999d").is_err());
+        assert!(parse_offset("-99999999w").is_err());
+        assert!(parse("{{date+99999999d}}").is_err());
+    }
+
+    #[test]
+    fn out_of_range_offsets_render_without_panicking() {
+        let variable = Variable::Date {
+            format: "%Y".to_string(),
+            offset: Duration::MAX,
+        };
+        let rendered = render(&[Token::Variable(variable)], false, &RenderContext::default());
+        assert_eq!(rendered, Local::now().format("%Y").to_string());
+    }
+
+    #[test]
+    fn escaped_braces_are_literal_text() {
+        assert_eq!(
+            parse(r"Hello \{{name}}, it is {{uuid}}"),
+            Ok(vec![
+                Token::Text("Hello {{name}}, it is ".to_string()),
+                Token::Variable(Variable::Uuid),
+            ])
+        );
+        assert_eq!(
+            parse(r#"format!("\{{}}")"#),
+            Ok(vec![Token::Text(r#"format!("{{}}")"#.to_string())])
+        );
+        assert_eq!(parse(r"\{{"), Ok(vec![Token::Text("{{".to_string())]));
+        assert_eq!(
+            parse(r"\{{{{uuid}}"),
+            Ok(vec![Token::Text("{{".to_string()), Token::Variable(Variable::Uuid)])
+        );
+        assert!(parse("{{name}}").is_err());
+        assert!(parse("{{").is_err());
+    }
+
+    #[test]
+    fn snippet_references_lists_included_triggers() {
+        let tokens = parse("Hi {{snippet:name}}, {{date}} {{snippet:sig}}").unwrap();
+        assert_eq!(snippet_references(&tokens), vec!["name", "sig"]);
+        assert!(snippet_references(&parse("no references").unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+use crate::snippet::{MatchMode, Snippet};
+use std::collections::HashMap;
+
+/// Characters that separate words for `MatchMode::WordStart` and
+/// `MatchMode::WholeWord` triggers.
+pub fn is_word_boundary(ch: char) -> bool {
+    !ch.is_alphanumeric() && ch != '_'
+}
+
+/// Case-folds a single character for trie lookups. Characters whose
+/// lowercase form is more than one character are kept as they are so that
+/// trie depth always equals the number of typed characters.
+fn fold(ch: char) -> char {
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(folded), None) => folded,
+        _ => ch,
+    }
+}
+
+/// In-memory index of snippet triggers. A reversed trie finds every trigger
+/// that is a suffix of the typed buffer by walking the buffer backwards once;
+/// a forward trie of the same triggers answers whether the buffer could still
+/// grow into a longer trigger. Both tries are keyed by case-folded characters
+/// so `Snippet::propagate_case` triggers match in any case; exact-case
+/// triggers are checked against the typed text afterwards.
+#[derive(Debug, Default)]
+pub struct TriggerIndex {
+    suffixes: TrieNode,
+    prefixes: TrieNode,
+    snippets: HashMap<String, Snippet>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<char, TrieNode>,
+    snippet_ids: Vec<String>,
+}
+
+impl TrieNode {
+    fn is_empty(&self) -> bool {
+        self.snippet_ids.is_empty() && self.children.is_empty()
+    }
+
+    fn insert(&mut self, path: impl Iterator<Item = char>, id: &str) {
+        let mut node = self;
+        for ch in path {
+            node = node.children.entry(fold(ch)).or_default();
+        }
+        node.snippet_ids.push(id.to_string());
+    }
+
+    /// Removes `id` from the node at the end of `path`, pruning nodes left
+    /// empty on the way back up. Returns whether this node is now empty.
+    fn remove(&mut self, path: &[char], id: &str) -> bool {
+        match path.split_first() {
+            None => {
+                self.snippet_ids.retain(|existing| existing != id);
+            }
+            Some((ch, rest)) => {
+                let key = fold(*ch);
+                let prune = match self.children.get_mut(&key) {
+                    Some(child) => child.remove(rest, id),
+                    None => false,
+                };
+                if prune {
+                    self.children.remove(&key);
+                }
+            }
+        }
+        self.is_empty()
+    }
+}
+
+/// The trigger chosen for the current buffer.
+#[derive(Debug)]
+pub struct TriggerMatch<'a> {
+    pub snippet: &'a Snippet,
+    /// The trigger as it was actually typed.
+    pub typed: String,
+    /// Number of buffer characters the trigger covers.
+    pub length: usize,
+    /// Separator typed after the trigger, either the delimiter that completed
+    /// it or the one ending a `MatchMode::WholeWord` trigger. It has already
+    /// reached the application and has to be removed and typed again after
+    /// the content.
+    pub trailing: Option<char>,
+    /// Whether the text ending in this trigger is also the start of a longer
+    /// trigger, so the engine should give the user a chance to finish it.
+    pub has_longer_candidate: bool,
+}
+
+impl TriggerIndex {
+    pub fn new(snippets: Vec<Snippet>) -> Self {
+        let mut index = Self::default();
+        for snippet in snippets {
+            index.insert(snippet);
+        }
+        index
+    }
+
+    /// Adds a snippet, replacing any previous entry with the same id.
+    pub fn insert(&mut self, snippet: Snippet) {
+        self.remove(&snippet.id);
+        if snippet.trigger.is_empty() {
+            return;
+        }
+
+        self.suffixes.insert(snippet.trigger.chars().rev(), &snippet.id);
+        self.prefixes.insert(snippet.trigger.chars(), &snippet.id);
+        self.snippets.insert(snippet.id.clone(), snippet);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(snippet) = self.snippets.remove(id) {
+            let path: Vec<char> = snippet.trigger.chars().collect();
+            self.prefixes.remove(&path, id);
+
+            let reversed: Vec<char> = path.into_iter().rev().collect();
+            self.suffixes.remove(&reversed, id);
+        }
+    }
+
+    /// Returns every snippet whose trigger is a suffix of `buffer`, shortest
+    /// trigger first. Exact-case triggers only match when typed in the same
+    /// case; `propagate_case` triggers match in any case.
+    pub fn suffix_matches(&self, buffer: &str) -> Vec<&Snippet> {
+        let mut matches = Vec::new();
+        let mut node = &self.suffixes;
+
+        for ch in buffer.chars().rev() {
+            node = match node.children.get(&fold(ch)) {
+                Some(child) => child,
+                None => break,
+            };
+            matches.extend(
+                node.snippet_ids
+                    .iter()
+                    .filter_map(|id| self.snippets.get(id))
+                    .filter(|snippet| snippet.propagate_case || buffer.ends_with(snippet.trigger.as_str())),
+            );
+        }
+
+        matches
+    }
+
+    /// Picks the trigger to expand for `buffer`. `starts_at_boundary` tells
+    /// whether the text before the buffer ends at a word boundary, and
+    /// `delimiter` is the delimiter being typed right after it, if any. The longest
+    /// allowed trigger wins; among matches of equal length one typed in the
+    /// trigger's exact case comes first, then `Snippet::priority` and finally
+    /// the trigger text so the choice never depends on database order.
+    pub fn resolve(&self, buffer: &str, starts_at_boundary: bool, delimiter: Option<char>) -> Option<TriggerMatch<'_>> {
+        let chars: Vec<char> = buffer.chars().collect();
+        let preceded_by_boundary = |end: usize, length: usize| match end.checked_sub(length + 1) {
+            Some(before) => is_word_boundary(chars[before]),
+            None => starts_at_boundary,
+        };
+        let typed_before = |end: usize, snippet: &Snippet| -> String {
+            chars[end - snippet.trigger.chars().count()..end].iter().collect()
+        };
+
+        let mut candidates: Vec<(&Snippet, String, Option<char>)> = self
+            .suffix_matches(buffer)
+            .into_iter()
+            .filter(|snippet| match snippet.match_mode {
+                MatchMode::Anywhere => true,
+                MatchMode::WordStart => preceded_by_boundary(chars.len(), snippet.trigger.chars().count()),
+                MatchMode::WholeWord => false,
+            })
+            .map(|snippet| (snippet, typed_before(chars.len(), snippet), delimiter))
+            .collect();
+
+        let separator = match (delimiter, chars.last()) {
+            (Some(delimiter), _) => Some((chars.len(), delimiter)),
+            (None, Some(&last)) if is_word_boundary(last) => Some((chars.len() - 1, last)),
+            _ => None,
+        };
+        if let Some((end, separator)) = separator {
+            let head: String = chars[..end].iter().collect();
+            candidates.extend(
+                self.suffix_matches(&head)
+                    .into_iter()
+                    .filter(|snippet| {
+                        snippet.match_mode == MatchMode::WholeWord
+                            && preceded_by_boundary(end, snippet.trigger.chars().count())
+                    })
+                    .map(|snippet| (snippet, typed_before(end, snippet), Some(separator))),
+            );
+        }
+
+        let (snippet, typed, trailing) = candidates.into_iter().max_by(|(a, a_typed, _), (b, b_typed, _)| {
+            a.trigger
+                .chars()
+                .count()
+                .cmp(&b.trigger.chars().count())
+                .then((*a_typed == a.trigger).cmp(&(*b_typed == b.trigger)))
+                .then(a.priority.cmp(&b.priority))
+                .then(b.trigger.cmp(&a.trigger))
+        })?;
+
+        let length = snippet.trigger.chars().count();
+        Some(TriggerMatch {
+            snippet,
+            typed,
+            length,
+            trailing,
+            has_longer_candidate: self.has_longer_candidate(buffer, length),
+        })
+    }
+
+    /// Whether `buffer` ends in a `MatchMode::WholeWord` trigger that will
+    /// match once a separator is typed, so the text must not be forgotten
+    /// while the user pauses before typing it.
+    pub fn awaits_separator(&self, buffer: &str, starts_at_boundary: bool) -> bool {
+        let chars: Vec<char> = buffer.chars().collect();
+        self.suffix_matches(buffer).into_iter().any(|snippet| {
+            let length = snippet.trigger.chars().count();
+            snippet.match_mode == MatchMode::WholeWord
+                && match chars.len().checked_sub(length + 1) {
+                    Some(before) => is_word_boundary(chars[before]),
+                    None => starts_at_boundary,
+                }
+        })
+    }
+
+    /// Whether some suffix of `buffer` at least `min_length` characters long
+    /// is a proper prefix of a trigger that could still match, exact-case
+    /// triggers only counting when the suffix was typed in their case.
+    fn has_longer_candidate(&self, buffer: &str, min_length: usize) -> bool {
+        let chars: Vec<char> = buffer.chars().collect();
+        if chars.len() < min_length {
+            return false;
+        }
+
+        (0..=chars.len() - min_length).any(|start| {
+            let mut node = &self.prefixes;
+            for ch in &chars[start..] {
+                node = match node.children.get(&fold(*ch)) {
+                    Some(child) => child,
+                    None => return false,
+                };
+            }
+            let typed: String = chars[start..].iter().collect();
+            node.children.values().any(|child| self.completes(child, &typed))
+        })
+    }
+
+    /// Whether a trigger at or below `node` can be typed starting with `typed`.
+    fn completes(&self, node: &TrieNode, typed: &str) -> bool {
+        node.snippet_ids
+            .iter()
+            .filter_map(|id| self.snippets.get(id))
+            .any(|snippet| snippet.propagate_case || snippet.trigger.starts_with(typed))
+            || node.children.values().any(|child| self.completes(child, typed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(trigger: &str) -> Snippet {
+        Snippet::new(trigger.to_string(), format!("<{}>", trigger), None, Vec::new(), false)
+    }
+
+    fn with_case(trigger: &str) -> Snippet {
+        Snippet {
+            propagate_case: true,
+            ..snippet(trigger)
+        }
+    }
+
+    fn with_mode(trigger: &str, match_mode: MatchMode) -> Snippet {
+        Snippet {
+            match_mode,
+            ..snippet(trigger)
+        }
+    }
+
+    fn triggers(matches: Vec<&Snippet>) -> Vec<&str> {
+        matches.into_iter().map(|snippet| snippet.trigger.as_str()).collect()
+    }
+
+    fn resolved(index: &TriggerIndex, buffer: &str) -> Option<String> {
+        index
+            .resolve(buffer, true, None)
+            .map(|found| found.snippet.trigger.clone())
+    }
+
+    #[test]
+    fn suffix_matches_are_shortest_first_and_fold_case_only_when_asked() {
+        let index = TriggerIndex::new(vec![snippet("ig"), with_case("sig"), snippet("brb")]);
+
+        assert_eq!(triggers(index.suffix_matches("my sig")), vec!["ig", "sig"]);
+        assert_eq!(triggers(index.suffix_matches("my SIG")), vec!["sig"]);
+        assert_eq!(triggers(index.suffix_matches("ok brb")), vec!["brb"]);
+        assert!(index.suffix_matches("ok BRB").is_empty());
+        assert!(index.suffix_matches("sigh").is_empty());
+    }
+
+    #[test]
+    fn remove_prunes_empty_nodes() {
+        let sig = snippet("sig");
+        let sign = snippet("sign");
+        let mut index = TriggerIndex::new(vec![sig.clone(), sign.clone()]);
+
+        index.remove(&sign.id);
+        assert_eq!(triggers(index.suffix_matches("sig")), vec!["sig"]);
+        assert!(index.suffix_matches("sign").is_empty());
+        assert!(!index.has_longer_candidate("sig", 3));
+
+        index.remove(&sig.id);
+        assert!(index.suffixes.is_empty());
+        assert!(index.prefixes.is_empty());
+        assert!(index.snippets.is_empty());
+    }
+
+    #[test]
+    fn insert_rekeys_a_renamed_trigger() {
+        let mut renamed = snippet("old");
+        let mut index = TriggerIndex::new(vec![renamed.clone()]);
+
+        renamed.trigger = "new".to_string();
+        index.insert(renamed);
+
+        assert!(index.suffix_matches("old").is_empty());
+        assert_eq!(triggers(index.suffix_matches("new")), vec!["new"]);
+        assert_eq!(index.snippets.len(), 1);
+    }
+
+    #[test]
+    fn resolve_prefers_longest_then_exact_case_then_priority_then_trigger() {
+        let index = TriggerIndex::new(vec![snippet("g"), snippet("sig")]);
+        assert_eq!(resolved(&index, "my sig").as_deref(), Some("sig"));
+
+        let index = TriggerIndex::new(vec![with_case("Sig"), with_case("sig")]);
+        assert_eq!(resolved(&index, "sig").as_deref(), Some("sig"));
+        assert_eq!(resolved(&index, "Sig").as_deref(), Some("Sig"));
+
+        let high = Snippet {
+            priority: 1,
+            ..with_case("sig")
+        };
+        let index = TriggerIndex::new(vec![with_case("SIG"), high]);
+        assert_eq!(resolved(&index, "sIg").as_deref(), Some("sig"));
+
+        let index = TriggerIndex::new(vec![with_case("sig"), with_case("SIG")]);
+        assert_eq!(resolved(&index, "sIg").as_deref(), Some("SIG"));
+    }
+
+    #[test]
+    fn resolve_reports_the_typed_case() {
+        let index = TriggerIndex::new(vec![with_case("sig")]);
+        let found = index.resolve("my SiG", true, None).unwrap();
+        assert_eq!(found.typed, "SiG");
+        assert_eq!(found.length, 3);
+    }
+
+    #[test]
+    fn word_start_needs_a_boundary_before_the_trigger() {
+        let index = TriggerIndex::new(vec![with_mode("ad", MatchMode::WordStart)]);
+
+        assert_eq!(resolved(&index, "a ad").as_deref(), Some("ad"));
+        assert_eq!(resolved(&index, "bad"), None);
+        assert!(index.resolve("ad", true, None).is_some());
+        assert!(index.resolve("ad", false, None).is_none());
+    }
+
+    #[test]
+    fn whole_word_needs_a_separator_after_the_trigger() {
+        let index = TriggerIndex::new(vec![with_mode("ad", MatchMode::WholeWord)]);
+
+        assert!(index.resolve("ad", true, None).is_none());
+        assert!(index.resolve("bad ", true, None).is_none());
+
+        let found = index.resolve("an ad ", true, None).unwrap();
+        assert_eq!(found.trailing, Some(' '));
+        assert_eq!(found.length, 2);
+
+        let found = index.resolve("ad", true, Some('.')).unwrap();
+        assert_eq!(found.trailing, Some('.'));
+    }
+
+    #[test]
+    fn whole_word_triggers_await_their_separator() {
+        let index = TriggerIndex::new(vec![with_mode("ad", MatchMode::WholeWord), snippet("sig")]);
+
+        assert!(index.awaits_separator("ad", true));
+        assert!(index.awaits_separator("an ad", false));
+        assert!(!index.awaits_separator("ad", false));
+        assert!(!index.awaits_separator("bad", true));
+        assert!(!index.awaits_separator("ad ", true));
+        assert!(!index.awaits_separator("sig", true));
+    }
+
+    #[test]
+    fn anywhere_triggers_carry_the_delimiter_as_trailing() {
+        let index = TriggerIndex::new(vec![snippet("sig")]);
+
+        let found = index.resolve("xsig", false, Some('\t')).unwrap();
+        assert_eq!(found.trailing, Some('\t'));
+        assert!(index.resolve("xsig", false, None).unwrap().trailing.is_none());
+    }
+
+    #[test]
+    fn has_longer_candidate_when_the_buffer_can_still_grow() {
+        let index = TriggerIndex::new(vec![snippet("sig"), snippet("sign")]);
+        assert!(index.resolve("sig", true, None).unwrap().has_longer_candidate);
+        assert!(!index.resolve("sign", true, None).unwrap().has_longer_candidate);
+
+        let index = TriggerIndex::new(vec![snippet("sig")]);
+        assert!(!index.resolve("sig", true, None).unwrap().has_longer_candidate);
+
+        let index = TriggerIndex::new(vec![snippet("g"), snippet("sigh")]);
+        assert!(index.resolve("my sig", true, None).unwrap().has_longer_candidate);
+        assert!(!index.has_longer_candidate("my sig", 4));
+    }
+
+    #[test]
+    fn exact_case_triggers_are_only_candidates_in_their_case() {
+        let index = TriggerIndex::new(vec![snippet("sig"), snippet("SIGN")]);
+        assert!(!index.resolve("sig", true, None).unwrap().has_longer_candidate);
+
+        let index = TriggerIndex::new(vec![snippet("sig"), with_case("SIGN")]);
+        assert!(index.resolve("sig", true, None).unwrap().has_longer_candidate);
+
+        let index = TriggerIndex::new(vec![with_case("sig"), snippet("SIGNATURE")]);
+        assert!(index.resolve("SIG", true, None).unwrap().has_longer_candidate);
+        assert!(!index.resolve("Sig", true, None).unwrap().has_longer_candidate);
+    }
+}
@@ -1,12 +1,18 @@
 import { ThemeProvider } from '@/components/theme-provider';
 import { Dashboard } from '@/components/Dashboard';
+import { FillInForm } from '@/components/FillInForm';
+import { getCurrentWindow } from '@tauri-apps/api/window';
 import { Toaster } from 'sonner';
 import "@/App.css";
 
+// Fill-in forms for snippets open in their own windows, labelled
+// `snippet-form-<id>` by the backend.
+const isFormWindow = getCurrentWindow().label.startsWith('snippet-form-');
+
 function App() {
   return (
     <ThemeProvider defaultTheme="light" storageKey="lemme-do-it-theme">
-      <Dashboard />
+      {isFormWindow ? <FillInForm /> : <Dashboard />}
       <Toaster position="top-right" richColors />
     </ThemeProvider>
   );
@@ -1,12 +1,13 @@
 import { useState, useEffect } from 'react';
 import { invoke } from "@tauri-apps/api/core"
+import { listen } from '@tauri-apps/api/event';
 import { Sidebar } from '@/components/Sidebar';
 import { Header } from '@/components/Header';
 import { SnippetList } from '@/components/SnippetList';
 import { SnippetEditor } from '@/components/SnippetEditor';
 import { EmptyState } from '@/components/EmptyState';
 import { StatsCards } from '@/components/StatsCard';
-import { Snippet } from '@/types';
+import { ExpansionAborted, Snippet } from '@/types';
 import { toast } from 'sonner';
 import { Settings } from '@/components/Settings';
 
@@ -37,6 +38,20 @@ export function Dashboard() {
     loadSnippets();
   }, []);
 
+  useEffect(() => {
+    const unlisten = listen<ExpansionAborted>('expansion-aborted', (event) => {
+      const { trigger, undo } = event.payload;
+      toast.warning(
+        undo
+          ? `Stopped restoring "${trigger}" because you typed or clicked`
+          : `Stopped expanding "${trigger}" because you typed or clicked`
+      );
+    });
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, []);
+
   useEffect(() => {
     let filtered = snippets;
 
@@ -0,0 +1,136 @@
+import { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card';
+import { Button } from '@/components/ui/button';
+import { Input } from '@/components/ui/input';
+import { Label } from '@/components/ui/label';
+import { Textarea } from '@/components/ui/textarea';
+import { Select, SelectContent, SelectItem, SelectTrigger, SelectValue } from '@/components/ui/select';
+import { toast } from 'sonner';
+import { FormRequest } from '@/types';
+
+export function FillInForm() {
+  const [request, setRequest] = useState<FormRequest | null>(null);
+  const [values, setValues] = useState<Record<string, string>>({});
+
+  useEffect(() => {
+    loadRequest();
+  }, []);
+
+  useEffect(() => {
+    const handleKeyDown = (event: KeyboardEvent) => {
+      if (event.key === 'Escape' && !event.defaultPrevented) {
+        handleCancel();
+      }
+    };
+    window.addEventListener('keydown', handleKeyDown);
+    return () => window.removeEventListener('keydown', handleKeyDown);
+  }, [request]);
+
+  const loadRequest = async () => {
+    try {
+      const pending = await invoke<FormRequest | null>('get_form_request');
+      if (!pending) {
+        return;
+      }
+      const initial: Record<string, string> = {};
+      for (const field of pending.fields) {
+        initial[field.name] = field.kind === 'choice' ? field.options[0] : '';
+      }
+      setValues(initial);
+      setRequest(pending);
+    } catch (error) {
+      console.error(error);
+      toast.error('Failed to load form');
+    }
+  };
+
+  const handleSubmit = async (event: React.FormEvent) => {
+    event.preventDefault();
+    if (!request) return;
+    try {
+      await invoke('submit_form', { id: request.id, values });
+    } catch (error) {
+      console.error(error);
+      toast.error(error as string);
+    }
+  };
+
+  const handleCancel = async () => {
+    if (!request) return;
+    try {
+      await invoke('cancel_form', { id: request.id });
+    } catch (error) {
+      console.error(error);
+    }
+  };
+
+  const setValue = (name: string, value: string) => {
+    setValues((current) => ({ ...current, [name]: value }));
+  };
+
+  if (!request) {
+    return (
+      <div className="flex items-center justify-center py-12">
+        <div className="text-muted-foreground">Loading...</div>
+      </div>
+    );
+  }
+
+  return (
+    <form onSubmit={handleSubmit} className="p-4">
+      <Card>
+        <CardHeader>
+          <CardTitle>
+            Fill in <code className="font-mono">{request.trigger}</code>
+          </CardTitle>
+          {request.description && <CardDescription>{request.description}</CardDescription>}
+        </CardHeader>
+        <CardContent className="space-y-4">
+          {request.fields.map((field, index) => (
+            <div key={field.name} className="space-y-2">
+              <Label htmlFor={field.name}>{field.name}</Label>
+              {field.kind === 'text' && (
+                <Input
+                  id={field.name}
+                  autoFocus={index === 0}
+                  value={values[field.name] ?? ''}
+                  onChange={(e) => setValue(field.name, e.target.value)}
+                />
+              )}
+              {field.kind === 'multi_line' && (
+                <Textarea
+                  id={field.name}
+                  autoFocus={index === 0}
+                  rows={4}
+                  value={values[field.name] ?? ''}
+                  onChange={(e) => setValue(field.name, e.target.value)}
+                />
+              )}
+              {field.kind === 'choice' && (
+                <Select value={values[field.name]} onValueChange={(value) => setValue(field.name, value)}>
+                  <SelectTrigger id={field.name} className="w-full">
+                    <SelectValue />
+                  </SelectTrigger>
+                  <SelectContent>
+                    {field.options.map((option) => (
+                      <SelectItem key={option} value={option}>
+                        {option}
+                      </SelectItem>
+                    ))}
+                  </SelectContent>
+                </Select>
+              )}
+            </div>
+          ))}
+          <div className="flex justify-end gap-2 pt-2">
+            <Button type="button" variant="outline" onClick={handleCancel}>
+              Cancel
+            </Button>
+            <Button type="submit">Insert</Button>
+          </div>
+        </CardContent>
+      </Card>
+    </form>
+  );
+}
@@ -8,8 +8,18 @@ export type Snippet = {
     created_at: number;
     updated_at: number;
     is_html: boolean;
+    priority: number;
+    match_mode: MatchMode;
+    expand_on_delimiter: boolean | null;
+    propagate_case: boolean;
+    plain_text: string | null;
+    insert_method: InsertMethod;
 }
 
+export type MatchMode = 'anywhere' | 'word_start' | 'whole_word';
+
+export type InsertMethod = 'type' | 'paste' | 'auto';
+
 export type Theme = 'dark' | 'light';
 
 export type ThemeProviderProps = {
@@ -28,6 +38,46 @@ export type SnippetSettings = {
   time_delay_ms: number;
   created_at: number;
   updated_at: number;
+  expand_on_delimiter: boolean;
+  delimiters: string;
+  suppress_after_undo: boolean;
+  clipboard_restore_delay_ms: number;
+  paste_threshold_chars: number;
+  pre_expansion_delay_ms: number;
+  key_delay_ms: number;
+  post_delete_delay_ms: number;
+  clipboard_settle_delay_ms: number;
+  paste_delay_ms: number;
+  post_expansion_delay_ms: number;
+}
+
+export type InsertionProfile = {
+  id: string;
+  app_class: string;
+  paste_chord: string;
+  insert_method: InsertMethod | null;
+  key_delay_ms: number | null;
+  paste_delay_ms: number | null;
+  clipboard_restore_delay_ms: number | null;
+  created_at: number;
+  updated_at: number;
+}
+
+export type ExpansionAborted = {
+  trigger: string;
+  undo: boolean;
+}
+
+export type FormField =
+  | { name: string; kind: 'text' }
+  | { name: string; kind: 'multi_line' }
+  | { name: string; kind: 'choice'; options: string[] };
+
+export interface FormRequest {
+  id: string;
+  trigger: string;
+  description: string | null;
+  fields: FormField[];
 }
 
 export interface SnippetListProps {